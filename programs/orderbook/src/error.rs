@@ -0,0 +1,177 @@
+//! Errors returned by the order book program.
+
+use {num_enum::IntoPrimitive, solana_program::program_error::ProgramError, thiserror::Error};
+
+/// Errors raised by the matching engine and instruction processor.
+///
+/// Discriminants are pinned explicitly, starting at
+/// [`ORDERBOOK_ERROR_BASE`], rather than left to derive order: a client
+/// decodes `ProgramError::Custom` back into one of these variants by
+/// its numeric code, so reordering the list here must never silently
+/// renumber an existing variant. Add new variants at the end, with the
+/// next unused number.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive)]
+#[repr(u32)]
+pub enum OrderbookError {
+    /// Catch-all used by paths that have not yet been given a dedicated
+    /// variant.
+    #[error("an orderbook error occurred")]
+    SomeError = ORDERBOOK_ERROR_BASE,
+
+    #[error("the event type tag on an `AnyEvent` did not match the requested event")]
+    EventTypeMismatch = ORDERBOOK_ERROR_BASE + 1,
+
+    #[error("an order with this order id is already resting on the book")]
+    DuplicateKey = ORDERBOOK_ERROR_BASE + 2,
+
+    #[error("max_base_lots and max_quote_lots must be positive")]
+    InvalidQuantity = ORDERBOOK_ERROR_BASE + 3,
+
+    #[error("a fill price/quantity calculation overflowed")]
+    MathError = ORDERBOOK_ERROR_BASE + 4,
+
+    #[error("the market is reduce-only or closing and is not accepting new orders")]
+    MarketNotAcceptingOrders = ORDERBOOK_ERROR_BASE + 5,
+
+    #[error("the book is full and the new order is not better priced than the worst resting order")]
+    OutOfSpace = ORDERBOOK_ERROR_BASE + 6,
+
+    /// Returned by [`EventQueue::push_back`](crate::state::event_queue::EventQueue::push_back)
+    /// when the queue is already at [`EVENT_QUEUE_CAPACITY`](crate::state::event_queue::EVENT_QUEUE_CAPACITY),
+    /// instead of the ambiguous `SomeError`.
+    #[error("the event queue is full")]
+    QueueFull = ORDERBOOK_ERROR_BASE + 7,
+
+    /// Returned by [`PerpMarket::validate`](crate::state::market::PerpMarket::validate)
+    /// when `base_lot_size` or `quote_lot_size` is not a positive power
+    /// of 10.
+    #[error("base_lot_size and quote_lot_size must be positive powers of 10")]
+    InvalidLotSize = ORDERBOOK_ERROR_BASE + 8,
+
+    /// Returned by [`OrderTree::try_compact`](crate::state::order_tree::OrderTree::try_compact)
+    /// when a handle in the price/time index has no corresponding slab
+    /// entry, an invariant that should never break on its own but is
+    /// worth surfacing as a clean error rather than panicking.
+    #[error("the order tree's index referenced a slab slot that wasn't occupied")]
+    CorruptNode = ORDERBOOK_ERROR_BASE + 9,
+
+    /// Returned by [`PerpMarket::check_order_price_band`](crate::state::market::PerpMarket::check_order_price_band)
+    /// when an order's price is further from the oracle price than
+    /// `max_price_deviation_bps` allows.
+    #[error("the order price is too far from the oracle price")]
+    SpotPriceBandExceeded = ORDERBOOK_ERROR_BASE + 10,
+
+    /// Returned by [`Orderbook::new_order`](crate::state::orderbook::Orderbook::new_order)
+    /// when a `CancelProvide` order still crosses one of its own resting
+    /// makers after `max_self_trade_cancels_per_place` cancellations, and
+    /// posting it as-is would leave the book crossed against itself. The
+    /// order is rejected outright rather than posted uncrossed-but-wrong;
+    /// the caller should place it again (or cancel the remaining makers
+    /// first) to make progress.
+    #[error("the self-trade cancel cap was hit with a same-owner maker still crossing")]
+    SelfTradeCancelCapExceeded = ORDERBOOK_ERROR_BASE + 11,
+
+    /// Returned by [`Orderbook::new_order`](crate::state::orderbook::Orderbook::new_order)
+    /// when `price_lots` is below [`MIN_PRICE_LOTS`](crate::state::orderbook::MIN_PRICE_LOTS)
+    /// for an order type whose price can rest on the book or act as a
+    /// matching-loop divisor (i.e. anything but `Market`). A non-positive
+    /// price would otherwise pack into a corrupt node key, or divide by
+    /// zero the moment a crossing order tried to match against it.
+    #[error("price_lots must be at least MIN_PRICE_LOTS")]
+    InvalidPrice = ORDERBOOK_ERROR_BASE + 12,
+
+    /// Returned by [`EventQueue::pop_front`](crate::state::event_queue::EventQueue::pop_front)
+    /// when the queue has nothing left to pop, instead of the ambiguous
+    /// `SomeError`.
+    #[error("the event queue is empty")]
+    QueueEmpty = ORDERBOOK_ERROR_BASE + 13,
+
+    /// Returned by [`Orderbook::new_order`](crate::state::orderbook::Orderbook::new_order)
+    /// when `self_trade_behavior` is `AbortTransaction` and the incoming
+    /// order would trade against one of its own resting orders.
+    #[error("the order would self-trade and self_trade_behavior is AbortTransaction")]
+    SelfTrade = ORDERBOOK_ERROR_BASE + 14,
+
+    /// Returned by [`Orderbook::new_order`](crate::state::orderbook::Orderbook::new_order)
+    /// when a `PostOnly` order's price would cross the opposing best
+    /// price, or a `PostOnlySlide` order would have to slide below
+    /// [`MIN_PRICE_LOTS`](crate::state::orderbook::MIN_PRICE_LOTS) to stop
+    /// crossing it.
+    #[error("a PostOnly order would have crossed the book")]
+    PostOnlyWouldCross = ORDERBOOK_ERROR_BASE + 15,
+
+    /// Returned by [`Orderbook::cancel_order`](crate::state::orderbook::Orderbook::cancel_order)
+    /// when `handle` doesn't refer to a resting order (already cancelled,
+    /// filled, or never valid).
+    #[error("no resting order was found at that handle")]
+    OrderNotFound = ORDERBOOK_ERROR_BASE + 16,
+
+    /// Returned by [`Orderbook::cancel_order_by_id`](crate::state::orderbook::Orderbook::cancel_order_by_id)
+    /// and [`Orderbook::reduce_order`](crate::state::orderbook::Orderbook::reduce_order)
+    /// when `order_id` doesn't refer to a resting order on `side`.
+    #[error("no resting order was found with that order id")]
+    OrderIdNotFound = ORDERBOOK_ERROR_BASE + 17,
+
+    /// Returned by [`Orderbook::cancel_order_by_id`](crate::state::orderbook::Orderbook::cancel_order_by_id)
+    /// and [`Orderbook::reduce_order`](crate::state::orderbook::Orderbook::reduce_order)
+    /// when the resting order found is owned by someone other than the
+    /// caller.
+    #[error("the resting order found is owned by someone else")]
+    NotOrderOwner = ORDERBOOK_ERROR_BASE + 18,
+}
+
+/// Numeric base for [`OrderbookError`]'s discriminants, offset away from
+/// 0 so its codes don't collide with `solana_program`'s own built-in
+/// `ProgramError` variants when decoded off-chain.
+pub const ORDERBOOK_ERROR_BASE: u32 = 6000;
+
+impl From<OrderbookError> for ProgramError {
+    fn from(e: OrderbookError) -> Self {
+        ProgramError::Custom(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pins every variant's numeric code so a future reordering of the
+    // enum (rather than the deliberate, append-only discriminants above)
+    // fails this test instead of silently shipping new codes to clients
+    // that pattern-match on the old ones.
+    #[test]
+    fn error_codes_are_pinned() {
+        let codes: Vec<u32> = [
+            OrderbookError::SomeError,
+            OrderbookError::EventTypeMismatch,
+            OrderbookError::DuplicateKey,
+            OrderbookError::InvalidQuantity,
+            OrderbookError::MathError,
+            OrderbookError::MarketNotAcceptingOrders,
+            OrderbookError::OutOfSpace,
+            OrderbookError::QueueFull,
+            OrderbookError::InvalidLotSize,
+            OrderbookError::CorruptNode,
+            OrderbookError::SpotPriceBandExceeded,
+            OrderbookError::SelfTradeCancelCapExceeded,
+            OrderbookError::InvalidPrice,
+            OrderbookError::QueueEmpty,
+            OrderbookError::SelfTrade,
+            OrderbookError::PostOnlyWouldCross,
+            OrderbookError::OrderNotFound,
+            OrderbookError::OrderIdNotFound,
+            OrderbookError::NotOrderOwner,
+        ]
+        .into_iter()
+        .map(u32::from)
+        .collect();
+
+        assert_eq!(
+            codes,
+            vec![
+                6000, 6001, 6002, 6003, 6004, 6005, 6006, 6007, 6008, 6009, 6010, 6011, 6012, 6013, 6014, 6015, 6016,
+                6017, 6018
+            ]
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Instruction builders for the order book program.
+
+use {
+    crate::{
+        id,
+        state::order::{OrderParams, OrderType, SelfTradeBehavior, Side, TimeInForce},
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        instruction::{AccountMeta, Instruction},
+        program_error::ProgramError,
+        pubkey::Pubkey,
+    },
+};
+
+/// The Borsh-serializable instruction-data form of [`OrderParams`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct PlaceOrderParams {
+    pub side: Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots: i64,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    pub client_order_id: u64,
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub strategy_id: u8,
+    pub reduce_only: bool,
+    pub reference_price_lots: Option<i64>,
+    pub max_price_deviation_bps: i64,
+}
+
+impl From<PlaceOrderParams> for OrderParams {
+    fn from(params: PlaceOrderParams) -> Self {
+        OrderParams {
+            side: params.side,
+            price_lots: params.price_lots,
+            max_base_lots: params.max_base_lots,
+            max_quote_lots: params.max_quote_lots,
+            order_type: params.order_type,
+            time_in_force: params.time_in_force,
+            client_order_id: params.client_order_id,
+            self_trade_behavior: params.self_trade_behavior,
+            strategy_id: params.strategy_id,
+            reduce_only: params.reduce_only,
+            reference_price_lots: params.reference_price_lots,
+            max_price_deviation_bps: params.max_price_deviation_bps,
+        }
+    }
+}
+
+impl From<OrderParams> for PlaceOrderParams {
+    fn from(params: OrderParams) -> Self {
+        PlaceOrderParams {
+            side: params.side,
+            price_lots: params.price_lots,
+            max_base_lots: params.max_base_lots,
+            max_quote_lots: params.max_quote_lots,
+            order_type: params.order_type,
+            time_in_force: params.time_in_force,
+            client_order_id: params.client_order_id,
+            self_trade_behavior: params.self_trade_behavior,
+            strategy_id: params.strategy_id,
+            reduce_only: params.reduce_only,
+            reference_price_lots: params.reference_price_lots,
+            max_price_deviation_bps: params.max_price_deviation_bps,
+        }
+    }
+}
+
+/// The set of instructions the order book program accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub enum OrderbookInstruction {
+    /// Accounts: `[market, bids, asks, event_queue, payer (signer)]`.
+    PlaceOrder { params: PlaceOrderParams },
+    /// Accounts: `[market, bids, asks, event_queue, payer (signer)]`.
+    CancelOrder { order_id: u128, side: Side },
+    /// Accounts: `[market, bids, asks, event_queue, payer (signer)]`.
+    CancelAllByOwner { limit: u8 },
+    /// Accounts: `[market, event_queue]`.
+    ConsumeEvents { limit: u16 },
+}
+
+impl OrderbookInstruction {
+    pub fn pack(&self) -> Vec<u8> {
+        borsh::to_vec(self).expect("OrderbookInstruction always serializes")
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        Self::try_from_slice(input).map_err(|_| ProgramError::InvalidInstructionData)
+    }
+}
+
+/// Builds a `PlaceOrder` instruction.
+///
+/// Accounts expected: `[market, bids, asks, event_queue, payer (signer)]`.
+///
+/// Serializes `params` through [`OrderbookInstruction::pack`] rather than
+/// hand-writing the wire bytes, so this can never drift from what
+/// [`OrderbookInstruction::unpack`] expects the way an earlier,
+/// field-by-field version of this function did.
+pub fn place_order(
+    market: Pubkey,
+    bids: Pubkey,
+    asks: Pubkey,
+    event_queue: Pubkey,
+    payer: Pubkey,
+    params: PlaceOrderParams,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(market, false),
+            AccountMeta::new(bids, false),
+            AccountMeta::new(asks, false),
+            AccountMeta::new(event_queue, false),
+            AccountMeta::new(payer, true),
+        ],
+        data: OrderbookInstruction::PlaceOrder { params }.pack(),
+    }
+}
+
+/// Builds a `CancelOrder` instruction.
+///
+/// Accounts expected: `[market, bids, asks, event_queue, payer (signer)]`.
+pub fn cancel_order(
+    market: Pubkey,
+    bids: Pubkey,
+    asks: Pubkey,
+    event_queue: Pubkey,
+    payer: Pubkey,
+    side: Side,
+    order_id: u128,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(market, false),
+            AccountMeta::new(bids, false),
+            AccountMeta::new(asks, false),
+            AccountMeta::new(event_queue, false),
+            AccountMeta::new(payer, true),
+        ],
+        data: OrderbookInstruction::CancelOrder { order_id, side }.pack(),
+    }
+}
+
+/// Builds a `ConsumeEvents` instruction.
+///
+/// Accounts expected: `[market, event_queue]`.
+pub fn consume_events(market: Pubkey, event_queue: Pubkey, limit: u16) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(market, false),
+            AccountMeta::new(event_queue, false),
+        ],
+        data: OrderbookInstruction::ConsumeEvents { limit }.pack(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pk(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    fn place_order_params() -> PlaceOrderParams {
+        PlaceOrderParams {
+            side: Side::Bid,
+            price_lots: 100,
+            max_base_lots: 10,
+            max_quote_lots: 1_000,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::GoodTillCancel,
+            client_order_id: 42,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            strategy_id: 3,
+            reduce_only: false,
+            reference_price_lots: None,
+            max_price_deviation_bps: 0,
+        }
+    }
+
+    #[test]
+    fn place_order_account_metas_and_data() {
+        let params = place_order_params();
+        let ix = place_order(pk(1), pk(2), pk(3), pk(4), pk(5), params);
+        assert_eq!(ix.program_id, id());
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(pk(1), false),
+                AccountMeta::new(pk(2), false),
+                AccountMeta::new(pk(3), false),
+                AccountMeta::new(pk(4), false),
+                AccountMeta::new(pk(5), true),
+            ]
+        );
+        assert_eq!(
+            OrderbookInstruction::unpack(&ix.data).unwrap(),
+            OrderbookInstruction::PlaceOrder { params }
+        );
+    }
+
+    #[test]
+    fn cancel_order_account_metas_and_data() {
+        let ix = cancel_order(pk(1), pk(2), pk(3), pk(4), pk(5), Side::Ask, 7);
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(pk(1), false),
+                AccountMeta::new(pk(2), false),
+                AccountMeta::new(pk(3), false),
+                AccountMeta::new(pk(4), false),
+                AccountMeta::new(pk(5), true),
+            ]
+        );
+        assert_eq!(
+            OrderbookInstruction::unpack(&ix.data).unwrap(),
+            OrderbookInstruction::CancelOrder { order_id: 7, side: Side::Ask }
+        );
+    }
+
+    #[test]
+    fn consume_events_account_metas_and_data() {
+        let ix = consume_events(pk(1), pk(2), 16);
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new_readonly(pk(1), false),
+                AccountMeta::new(pk(2), false),
+            ]
+        );
+        assert_eq!(
+            OrderbookInstruction::unpack(&ix.data).unwrap(),
+            OrderbookInstruction::ConsumeEvents { limit: 16 }
+        );
+    }
+
+    fn assert_round_trips(instruction: OrderbookInstruction) {
+        let packed = instruction.pack();
+        assert_eq!(OrderbookInstruction::unpack(&packed).unwrap(), instruction);
+    }
+
+    #[test]
+    fn place_order_round_trips() {
+        assert_round_trips(OrderbookInstruction::PlaceOrder {
+            params: PlaceOrderParams {
+                side: Side::Bid,
+                price_lots: 100,
+                max_base_lots: 10,
+                max_quote_lots: 1_000,
+                order_type: OrderType::Limit,
+                time_in_force: TimeInForce::GoodTillCancel,
+                client_order_id: 1,
+                self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                strategy_id: 3,
+                reduce_only: false,
+                reference_price_lots: None,
+                max_price_deviation_bps: 0,
+            },
+        });
+    }
+
+    #[test]
+    fn cancel_order_round_trips() {
+        assert_round_trips(OrderbookInstruction::CancelOrder {
+            order_id: 7,
+            side: Side::Ask,
+        });
+    }
+
+    #[test]
+    fn cancel_all_by_owner_round_trips() {
+        assert_round_trips(OrderbookInstruction::CancelAllByOwner { limit: 8 });
+    }
+
+    #[test]
+    fn consume_events_round_trips() {
+        assert_round_trips(OrderbookInstruction::ConsumeEvents { limit: 16 });
+    }
+}
@@ -0,0 +1,13 @@
+//! An on-chain central limit order book (CLOB) program.
+//!
+//! The book is split into two [`state::BookSide`]s (bids and asks), each
+//! backed by a price/time-ordered [`state::OrderTree`]. Matching, event
+//! emission and instruction handling build on top of those primitives.
+
+pub mod error;
+pub mod instruction;
+#[cfg(feature = "serde")]
+pub mod serde_helpers;
+pub mod state;
+
+solana_program::declare_id!("8W3RZYv2mmkrd51fCgQ6HWRzyd4JHsaWA9Uk79cQW8sA");
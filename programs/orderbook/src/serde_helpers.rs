@@ -0,0 +1,23 @@
+//! `serde` helpers for types that don't have a JSON-friendly
+//! representation by default. Only compiled with the `serde` feature.
+
+use {
+    serde::{de::Error as _, Deserialize, Deserializer, Serializer},
+    solana_program::pubkey::Pubkey,
+    std::str::FromStr,
+};
+
+/// Serializes a [`Pubkey`] as its base58 string form instead of the raw
+/// byte array `Pubkey`'s own `Serialize` impl produces.
+pub mod pubkey_as_base58 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&pubkey.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Pubkey::from_str(&s).map_err(D::Error::custom)
+    }
+}
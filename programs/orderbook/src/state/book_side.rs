@@ -0,0 +1,1028 @@
+//! One side (bids or asks) of an [`Orderbook`](super::orderbook::Orderbook).
+
+use {
+    crate::{
+        error::OrderbookError,
+        state::{
+            order::{Order, SelfTradeBehavior, Side},
+            order_tree::{Handle, OrderTree},
+            units::{BaseLots, PriceLots, QuoteLots},
+        },
+    },
+    solana_program::pubkey::Pubkey,
+};
+
+/// The resting orders on one side of the book.
+pub struct BookSide {
+    tree: OrderTree,
+}
+
+/// A single price level's quantity change, as produced by
+/// [`BookSide::diff`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LevelChange {
+    pub price_lots: i64,
+    /// Positive if the level's quantity grew since the earlier snapshot,
+    /// negative if it shrank (a negative value equal to the earlier
+    /// snapshot's full quantity at that price means the level vanished).
+    pub quantity_delta: i64,
+}
+
+impl BookSide {
+    pub fn new(side: Side) -> Self {
+        Self {
+            tree: OrderTree::new(side),
+        }
+    }
+
+    pub fn side(&self) -> Side {
+        self.tree.side()
+    }
+
+    pub fn tree(&self) -> &OrderTree {
+        &self.tree
+    }
+
+    pub fn tree_mut(&mut self) -> &mut OrderTree {
+        &mut self.tree
+    }
+
+    /// The highest-priority resting order, if any.
+    pub fn best_order(&self) -> Option<&Order> {
+        self.tree.best().map(|(_, order)| order)
+    }
+
+    /// The price, in lots, of the highest-priority resting order.
+    pub fn best_price(&self) -> Option<i64> {
+        self.best_order().map(|order| order.price_lots)
+    }
+
+    pub fn worst_order(&self) -> Option<&Order> {
+        self.tree.worst().map(|(_, order)| order)
+    }
+
+    /// The lowest resting price on this side, regardless of whether
+    /// that's the best or the worst price for this side.
+    pub fn min_price(&self) -> Option<i64> {
+        match self.side() {
+            Side::Bid => self.worst_order(),
+            Side::Ask => self.best_order(),
+        }
+        .map(|order| order.price_lots)
+    }
+
+    /// The highest resting price on this side, regardless of whether
+    /// that's the best or the worst price for this side.
+    pub fn max_price(&self) -> Option<i64> {
+        match self.side() {
+            Side::Bid => self.best_order(),
+            Side::Ask => self.worst_order(),
+        }
+        .map(|order| order.price_lots)
+    }
+
+    /// All resting orders on this side in priority order (best first),
+    /// including ones whose time-in-force has already elapsed.
+    pub fn iter_all_including_invalid(&self) -> impl Iterator<Item = &Order> {
+        self.tree.iter().map(|(_, order)| order)
+    }
+
+    /// Like [`iter_all_including_invalid`](Self::iter_all_including_invalid),
+    /// but pairs each order with its [`OrderType`], so a decoder that
+    /// treats different order types differently doesn't have to read
+    /// `order.order_type` back out itself.
+    ///
+    /// `BookSide` only ever holds one [`OrderTree`], and this crate has
+    /// no oracle-pegged order type — so an order's `OrderType` is the
+    /// only distinguishing "source" label anything here actually has;
+    /// there is no second tree to disambiguate between.
+    pub fn iter_with_order_type(&self) -> impl Iterator<Item = (crate::state::order::OrderType, &Order)> {
+        self.iter_all_including_invalid().map(|order| (order.order_type, order))
+    }
+
+    /// `owner`'s non-expired resting orders on this side, best first, as
+    /// a lazy iterator so a caller that only needs e.g. the first match
+    /// (via `.next()` or `.take(1)`) doesn't force a scan of the whole
+    /// side.
+    pub fn iter_owner<'a>(&'a self, owner: &'a Pubkey, now_ts: i64) -> impl Iterator<Item = &'a Order> + 'a {
+        self.iter_all_including_invalid()
+            .filter(move |order| &order.owner == owner && !order.is_expired(now_ts))
+    }
+
+    /// The best valid (non-expired) price on this side not owned by
+    /// `owner`, as of `now_ts`. Lets a maker checking the "real" best
+    /// opposing price ignore its own resting orders, e.g. under
+    /// [`SelfTradeBehavior::CancelProvide`], where they'd otherwise never
+    /// actually trade against the incoming order.
+    pub fn best_price_excluding(&self, owner: &Pubkey, now_ts: i64) -> Option<i64> {
+        self.iter_all_including_invalid()
+            .find(|order| !order.is_expired(now_ts) && &order.owner != owner)
+            .map(|order| order.price_lots)
+    }
+
+    /// The worst resting price on this side as of `now_ts`.
+    ///
+    /// When `include_invalid` is `false`, orders that have already
+    /// expired are skipped, since `remove_by_handle` only happens lazily
+    /// during matching and an expired order may still be sitting in the
+    /// tree.
+    pub fn worst_price(&self, now_ts: i64, include_invalid: bool) -> Option<i64> {
+        if include_invalid {
+            return self.worst_order().map(|order| order.price_lots);
+        }
+        self.iter_all_including_invalid()
+            .filter(|order| !order.is_expired(now_ts))
+            .last()
+            .map(|order| order.price_lots)
+    }
+
+    /// Sums the resting base-lot quantity and quote-lot notional
+    /// (quantity * price) across all valid (non-expired) orders on this
+    /// side as of `now_ts`.
+    ///
+    /// Accumulates in `i128` to avoid overflow while summing, then
+    /// saturates the result back down to `i64` rather than panicking or
+    /// wrapping on a pathologically large book.
+    pub fn totals(&self, now_ts: i64) -> (i64, i64) {
+        let (base, quote) = self
+            .iter_all_including_invalid()
+            .filter(|order| !order.is_expired(now_ts))
+            .fold((0i128, 0i128), |(base, quote), order| {
+                (
+                    base + order.quantity_lots as i128,
+                    quote + order.quantity_lots as i128 * order.price_lots as i128,
+                )
+            });
+        (
+            base.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            quote.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+        )
+    }
+
+    /// Estimates how many base lots of `quantity` a taker would actually
+    /// match against this side, without mutating the book.
+    ///
+    /// Expired orders are skipped, as they would be during real matching.
+    /// `self_owner`/`self_trade` model how a taker's own resting orders
+    /// are handled: `DecrementTake` counts them like any other maker,
+    /// `CancelProvide` skips them as if they weren't there, and
+    /// `AbortTransaction` fails the whole estimate the moment one is
+    /// encountered, mirroring [`Orderbook::new_order`](super::orderbook::Orderbook::new_order)'s behavior.
+    pub fn taker_estimate(
+        &self,
+        quantity: BaseLots,
+        now_ts: i64,
+        self_owner: Option<&Pubkey>,
+        self_trade: SelfTradeBehavior,
+    ) -> Result<BaseLots, OrderbookError> {
+        let quantity = quantity.get();
+        let mut matched = 0i64;
+        for order in self.iter_all_including_invalid() {
+            if matched >= quantity {
+                break;
+            }
+            if order.is_expired(now_ts) {
+                continue;
+            }
+            if self_owner == Some(&order.owner) {
+                match self_trade {
+                    SelfTradeBehavior::CancelProvide => continue,
+                    SelfTradeBehavior::AbortTransaction => return Err(OrderbookError::SomeError),
+                    SelfTradeBehavior::DecrementTake => {}
+                }
+            }
+            matched += (quantity - matched).min(order.quantity_lots);
+        }
+        Ok(BaseLots(matched))
+    }
+
+    /// Like [`taker_estimate`](Self::taker_estimate), but also stops the
+    /// walk once `max_reduce_base_lots` matched base lots are reached.
+    ///
+    /// This crate has no other pre-trade sizing helper that knows about
+    /// `reduce_only` for the same reason [`Order::reduce_only`](crate::state::order::Order::reduce_only)'s
+    /// doc comment gives: the matching engine doesn't track positions, so
+    /// it can't derive a reduce cap on its own. A position-aware caller
+    /// estimating a reduce-only order supplies the remaining reduceable
+    /// size here instead.
+    pub fn taker_estimate_with_reduce_cap(
+        &self,
+        quantity: BaseLots,
+        max_reduce_base_lots: BaseLots,
+        now_ts: i64,
+        self_owner: Option<&Pubkey>,
+        self_trade: SelfTradeBehavior,
+    ) -> Result<BaseLots, OrderbookError> {
+        let capped_quantity = quantity.get().min(max_reduce_base_lots.get()).max(0);
+        self.taker_estimate(BaseLots(capped_quantity), now_ts, self_owner, self_trade)
+    }
+
+    /// The price at which cumulative resting quantity first reaches
+    /// `fraction` of this side's total, walking from the best price
+    /// outward. `fraction` is clamped to `(0, 1]`. `None` on an empty
+    /// side.
+    ///
+    /// Useful for risk visualization (e.g. "price at which half the
+    /// book's depth is consumed").
+    pub fn price_at_depth_fraction(&self, fraction: f64, now_ts: i64) -> Option<PriceLots> {
+        let fraction = if fraction <= 0.0 { f64::MIN_POSITIVE } else { fraction.min(1.0) };
+        let (total, _) = self.totals(now_ts);
+        if total <= 0 {
+            return None;
+        }
+        let target = (fraction * total as f64).ceil() as i64;
+        let mut cumulative = 0i64;
+        for order in self.iter_all_including_invalid() {
+            if order.is_expired(now_ts) {
+                continue;
+            }
+            cumulative += order.quantity_lots;
+            if cumulative >= target {
+                return Some(PriceLots(order.price_lots));
+            }
+        }
+        None
+    }
+
+    /// Base lots matchable against this side at `limit_price_lots` or
+    /// better, capped by `max_quote_lots` of notional, as of `now_ts`.
+    ///
+    /// Mirrors [`Orderbook::new_order`](super::orderbook::Orderbook::new_order)'s
+    /// dual base/quote stopping condition, so it gives accurate pre-trade
+    /// sizing for a quote-constrained taker instead of only accounting
+    /// for the price limit. Expired orders are skipped.
+    pub fn quantity_at_price_with_quote_cap(
+        &self,
+        limit_price_lots: PriceLots,
+        max_quote_lots: QuoteLots,
+        now_ts: i64,
+    ) -> BaseLots {
+        self.quantity_at_price_with_quote_cap_bounded(limit_price_lots, max_quote_lots, now_ts, usize::MAX)
+            .0
+    }
+
+    /// Like [`quantity_at_price_with_quote_cap`](Self::quantity_at_price_with_quote_cap),
+    /// but never examines more than `max_iterations` resting orders,
+    /// bounding the compute an on-chain caller burns on a deep side
+    /// instead of walking it in full. Returns `(matched, truncated)`:
+    /// `truncated` is `true` when the cap was hit before the walk would
+    /// have stopped on its own, meaning the returned quantity is only a
+    /// lower bound on what a full walk would report.
+    pub fn quantity_at_price_with_quote_cap_bounded(
+        &self,
+        limit_price_lots: PriceLots,
+        max_quote_lots: QuoteLots,
+        now_ts: i64,
+        max_iterations: usize,
+    ) -> (BaseLots, bool) {
+        let limit_price_lots = limit_price_lots.get();
+        let max_quote_lots = max_quote_lots.get();
+        let taker_side = self.side().invert_side();
+        let mut matched_base = 0i64;
+        let mut matched_quote = 0i64;
+        for (visited, order) in self.iter_all_including_invalid().enumerate() {
+            if visited >= max_iterations {
+                return (BaseLots(matched_base), true);
+            }
+            if order.is_expired(now_ts) {
+                continue;
+            }
+            if !crate::state::order::Side::would_cross(taker_side, limit_price_lots, order.price_lots) {
+                return (BaseLots(matched_base), false);
+            }
+            let affordable_base = max_quote_lots.saturating_sub(matched_quote) / order.price_lots;
+            if affordable_base <= 0 {
+                return (BaseLots(matched_base), false);
+            }
+            let match_quantity = order.quantity_lots.min(affordable_base);
+            matched_base = matched_base.saturating_add(match_quantity);
+            // Saturating rather than the matching loop's `checked_` +
+            // `?` (see `Orderbook::checked_match_quote_lots`): this is a
+            // read-only sizing estimate with no `Result` in its return
+            // type, so an adversarial price/quantity pair should degrade
+            // to a saturated (still-conservative-enough) answer instead
+            // of panicking or silently wrapping.
+            matched_quote = matched_quote.saturating_add(match_quantity.saturating_mul(order.price_lots));
+        }
+        (BaseLots(matched_base), false)
+    }
+
+    /// Orders on this side at `limit_price_lots` or better, best first,
+    /// stopping as soon as one falls past the limit. Expired orders are
+    /// skipped. A building block for callers that want a custom
+    /// aggregation over the same walk [`quantity_at_price_with_quote_cap`](Self::quantity_at_price_with_quote_cap)
+    /// does, without reimplementing its stopping condition.
+    pub fn iter_until_price<'a>(&'a self, limit_price_lots: i64, now_ts: i64) -> impl Iterator<Item = &'a Order> + 'a {
+        let taker_side = self.side().invert_side();
+        self.iter_all_including_invalid()
+            .filter(move |order| !order.is_expired(now_ts))
+            .take_while(move |order| crate::state::order::Side::would_cross(taker_side, limit_price_lots, order.price_lots))
+    }
+
+    /// Distinct occupied prices on this side as of `now_ts`, best first.
+    /// Expired orders are skipped. Orders are already grouped by price
+    /// in tree order, so this only needs to dedupe consecutive equal
+    /// prices rather than sorting.
+    pub fn price_levels(&self, now_ts: i64) -> Vec<i64> {
+        let mut levels = Vec::new();
+        for order in self.iter_all_including_invalid() {
+            if order.is_expired(now_ts) {
+                continue;
+            }
+            if levels.last() != Some(&order.price_lots) {
+                levels.push(order.price_lots);
+            }
+        }
+        levels
+    }
+
+    /// Aggregated `(price_lots, quantity_lots)` levels on this side, best
+    /// first. Expired orders are skipped, and orders sharing a price are
+    /// summed into one level. Building block for [`diff`](Self::diff).
+    pub fn to_levels(&self, now_ts: i64) -> Vec<(i64, i64)> {
+        let mut levels: Vec<(i64, i64)> = Vec::new();
+        for order in self.iter_all_including_invalid() {
+            if order.is_expired(now_ts) {
+                continue;
+            }
+            match levels.last_mut() {
+                Some((price, qty)) if *price == order.price_lots => *qty += order.quantity_lots,
+                _ => levels.push((order.price_lots, order.quantity_lots)),
+            }
+        }
+        levels
+    }
+
+    /// Per-price-level quantity changes between `self` (the newer
+    /// snapshot) and `other` (an earlier snapshot of the same side), for
+    /// an incremental market-data feed that wants to publish deltas
+    /// instead of re-sending the whole book. A level present in only one
+    /// snapshot is reported against an implicit quantity of zero on the
+    /// other side. Built on [`to_levels`](Self::to_levels).
+    pub fn diff(&self, other: &BookSide, now_ts: i64) -> Vec<LevelChange> {
+        debug_assert_eq!(self.side(), other.side(), "diffing snapshots of different sides");
+
+        let mut deltas: std::collections::BTreeMap<i64, i64> = std::collections::BTreeMap::new();
+        for (price_lots, quantity_lots) in other.to_levels(now_ts) {
+            *deltas.entry(price_lots).or_insert(0) -= quantity_lots;
+        }
+        for (price_lots, quantity_lots) in self.to_levels(now_ts) {
+            *deltas.entry(price_lots).or_insert(0) += quantity_lots;
+        }
+
+        let mut changes: Vec<LevelChange> = deltas
+            .into_iter()
+            .filter(|(_, quantity_delta)| *quantity_delta != 0)
+            .map(|(price_lots, quantity_delta)| LevelChange { price_lots, quantity_delta })
+            .collect();
+        match self.side() {
+            Side::Bid => changes.sort_by(|a, b| b.price_lots.cmp(&a.price_lots)),
+            Side::Ask => changes.sort_by(|a, b| a.price_lots.cmp(&b.price_lots)),
+        }
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.leaf_count() == 0
+    }
+
+    /// The maximum number of orders this side can hold at once.
+    pub fn capacity(&self) -> usize {
+        self.tree.capacity()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.tree.is_full()
+    }
+
+    /// Fraction of `capacity` currently occupied, in `[0.0, 1.0]`. Useful
+    /// for operators monitoring how close a side is to needing evictions.
+    pub fn occupancy(&self) -> f64 {
+        self.tree.leaf_count() as f64 / self.capacity() as f64
+    }
+
+    pub fn remove_by_handle(&mut self, handle: Handle) -> Option<Order> {
+        self.tree.remove_by_handle(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::state::order::{OrderType, TimeInForce},
+        solana_program::pubkey::Pubkey,
+    };
+
+    fn order(order_id: u128, price_lots: i64, time_in_force: TimeInForce) -> Order {
+        order_with_owner(order_id, price_lots, time_in_force, Pubkey::new_from_array([1; 32]))
+    }
+
+    fn order_with_owner(order_id: u128, price_lots: i64, time_in_force: TimeInForce, owner: Pubkey) -> Order {
+        Order {
+            order_id,
+            owner,
+            side: Side::Bid,
+            price_lots,
+            quantity_lots: 1,
+            order_type: OrderType::Limit,
+            time_in_force,
+            timestamp: 0,
+            client_order_id: 0,
+            strategy_id: 0,
+        }
+    }
+
+    #[test]
+    fn worst_price_matches_last_of_iter_all_including_invalid() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order(1, 90, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(2, 95, TimeInForce::GoodTillCancel)).unwrap();
+
+        let expected = bids.iter_all_including_invalid().last().unwrap().price_lots;
+        assert_eq!(bids.worst_price(0, true), Some(expected));
+        assert_eq!(expected, 90);
+    }
+
+    #[test]
+    fn worst_price_skips_expired_orders_when_not_including_invalid() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut()
+            .insert(order(1, 90, TimeInForce::GoodTillTime { expiry_ts: 10 }))
+            .unwrap();
+        bids.tree_mut().insert(order(2, 95, TimeInForce::GoodTillCancel)).unwrap();
+
+        assert_eq!(bids.worst_price(20, true), Some(90));
+        assert_eq!(bids.worst_price(20, false), Some(95));
+    }
+
+    #[test]
+    fn iter_with_order_type_labels_each_order_with_its_own_type() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order(1, 100, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut()
+            .insert(Order {
+                order_type: OrderType::PostOnly,
+                ..order(2, 99, TimeInForce::GoodTillCancel)
+            })
+            .unwrap();
+
+        let labels: Vec<(OrderType, u128)> = bids
+            .iter_with_order_type()
+            .map(|(order_type, order)| (order_type, order.order_id))
+            .collect();
+        assert_eq!(labels, vec![(OrderType::Limit, 1), (OrderType::PostOnly, 2)]);
+    }
+
+    #[test]
+    fn totals_sums_quantity_and_notional_across_levels() {
+        let mut bids = BookSide::new(Side::Bid);
+        let mut a = order(1, 90, TimeInForce::GoodTillCancel);
+        a.quantity_lots = 3;
+        bids.tree_mut().insert(a).unwrap();
+        let mut b = order(2, 95, TimeInForce::GoodTillCancel);
+        b.quantity_lots = 2;
+        bids.tree_mut().insert(b).unwrap();
+
+        // 3 @ 90 + 2 @ 95 = 5 base lots, 270 + 190 = 460 quote lots.
+        assert_eq!(bids.totals(0), (5, 460));
+    }
+
+    #[test]
+    fn totals_skips_expired_orders() {
+        let mut bids = BookSide::new(Side::Bid);
+        let mut expired = order(1, 90, TimeInForce::GoodTillTime { expiry_ts: 10 });
+        expired.quantity_lots = 3;
+        bids.tree_mut().insert(expired).unwrap();
+        let mut live = order(2, 95, TimeInForce::GoodTillCancel);
+        live.quantity_lots = 2;
+        bids.tree_mut().insert(live).unwrap();
+
+        assert_eq!(bids.totals(20), (2, 190));
+    }
+
+    #[test]
+    fn taker_estimate_decrement_take_counts_self() {
+        let mut bids = BookSide::new(Side::Bid);
+        let me = Pubkey::new_from_array([9; 32]);
+        let mut mine = order_with_owner(1, 95, TimeInForce::GoodTillCancel, me);
+        mine.quantity_lots = 3;
+        bids.tree_mut().insert(mine).unwrap();
+        let mut other = order(2, 90, TimeInForce::GoodTillCancel);
+        other.quantity_lots = 4;
+        bids.tree_mut().insert(other).unwrap();
+
+        let matched = bids
+            .taker_estimate(BaseLots(5), 0, Some(&me), SelfTradeBehavior::DecrementTake)
+            .unwrap();
+        assert_eq!(matched, BaseLots(5));
+    }
+
+    #[test]
+    fn taker_estimate_cancel_provide_skips_self() {
+        let mut bids = BookSide::new(Side::Bid);
+        let me = Pubkey::new_from_array([9; 32]);
+        let mut mine = order_with_owner(1, 95, TimeInForce::GoodTillCancel, me);
+        mine.quantity_lots = 3;
+        bids.tree_mut().insert(mine).unwrap();
+        let mut other = order(2, 90, TimeInForce::GoodTillCancel);
+        other.quantity_lots = 4;
+        bids.tree_mut().insert(other).unwrap();
+
+        let matched = bids
+            .taker_estimate(BaseLots(5), 0, Some(&me), SelfTradeBehavior::CancelProvide)
+            .unwrap();
+        assert_eq!(matched, BaseLots(4));
+    }
+
+    #[test]
+    fn taker_estimate_abort_transaction_errors_on_self() {
+        let mut bids = BookSide::new(Side::Bid);
+        let me = Pubkey::new_from_array([9; 32]);
+        let mine = order_with_owner(1, 95, TimeInForce::GoodTillCancel, me);
+        bids.tree_mut().insert(mine).unwrap();
+
+        assert_eq!(
+            bids.taker_estimate(BaseLots(5), 0, Some(&me), SelfTradeBehavior::AbortTransaction)
+                .unwrap_err(),
+            OrderbookError::SomeError
+        );
+    }
+
+    #[test]
+    fn taker_estimate_with_reduce_cap_binds_before_liquidity_is_exhausted() {
+        let mut bids = BookSide::new(Side::Bid);
+        let mut a = order(1, 100, TimeInForce::GoodTillCancel);
+        a.quantity_lots = 10;
+        bids.tree_mut().insert(a).unwrap();
+
+        // Plenty of resting liquidity and requested quantity, but the
+        // reduce cap is the binding constraint.
+        let matched = bids
+            .taker_estimate_with_reduce_cap(BaseLots(10), BaseLots(3), 0, None, SelfTradeBehavior::DecrementTake)
+            .unwrap();
+        assert_eq!(matched, BaseLots(3));
+    }
+
+    #[test]
+    fn taker_estimate_with_reduce_cap_matches_taker_estimate_when_the_cap_never_binds() {
+        let mut bids = BookSide::new(Side::Bid);
+        let mut a = order(1, 100, TimeInForce::GoodTillCancel);
+        a.quantity_lots = 2;
+        bids.tree_mut().insert(a).unwrap();
+
+        let matched = bids
+            .taker_estimate_with_reduce_cap(BaseLots(10), BaseLots(1_000), 0, None, SelfTradeBehavior::DecrementTake)
+            .unwrap();
+        assert_eq!(matched, BaseLots(2));
+    }
+
+    fn book_for_depth_fraction() -> BookSide {
+        let mut bids = BookSide::new(Side::Bid);
+        let mut a = order(1, 100, TimeInForce::GoodTillCancel);
+        a.quantity_lots = 4;
+        bids.tree_mut().insert(a).unwrap();
+        let mut b = order(2, 99, TimeInForce::GoodTillCancel);
+        b.quantity_lots = 3;
+        bids.tree_mut().insert(b).unwrap();
+        let mut c = order(3, 98, TimeInForce::GoodTillCancel);
+        c.quantity_lots = 3;
+        bids.tree_mut().insert(c).unwrap();
+        bids
+    }
+
+    #[test]
+    fn price_at_depth_fraction_quarter() {
+        assert_eq!(book_for_depth_fraction().price_at_depth_fraction(0.25, 0), Some(PriceLots(100)));
+    }
+
+    #[test]
+    fn price_at_depth_fraction_half() {
+        assert_eq!(book_for_depth_fraction().price_at_depth_fraction(0.5, 0), Some(PriceLots(99)));
+    }
+
+    #[test]
+    fn price_at_depth_fraction_full() {
+        assert_eq!(book_for_depth_fraction().price_at_depth_fraction(1.0, 0), Some(PriceLots(98)));
+    }
+
+    #[test]
+    fn price_at_depth_fraction_is_none_for_empty_side() {
+        let bids = BookSide::new(Side::Bid);
+        assert_eq!(bids.price_at_depth_fraction(0.5, 0), None);
+    }
+
+    #[test]
+    fn price_levels_dedupes_repeated_prices_best_first() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order(1, 100, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(2, 100, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(3, 99, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(4, 98, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(5, 98, TimeInForce::GoodTillCancel)).unwrap();
+
+        assert_eq!(bids.price_levels(0), vec![100, 99, 98]);
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed_levels() {
+        let mut before = BookSide::new(Side::Bid);
+        before.tree_mut().insert(order(1, 100, TimeInForce::GoodTillCancel)).unwrap();
+        let mut shrinking = order(2, 99, TimeInForce::GoodTillCancel);
+        shrinking.quantity_lots = 5;
+        before.tree_mut().insert(shrinking).unwrap();
+
+        let mut after = BookSide::new(Side::Bid);
+        // order 1 (price 100) is gone entirely.
+        let mut shrunk = order(2, 99, TimeInForce::GoodTillCancel);
+        shrunk.quantity_lots = 2;
+        after.tree_mut().insert(shrunk).unwrap();
+        // A brand-new level at 98.
+        after.tree_mut().insert(order(3, 98, TimeInForce::GoodTillCancel)).unwrap();
+
+        let changes = after.diff(&before, 0);
+        assert_eq!(
+            changes,
+            vec![
+                LevelChange { price_lots: 100, quantity_delta: -1 },
+                LevelChange { price_lots: 99, quantity_delta: -3 },
+                LevelChange { price_lots: 98, quantity_delta: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_against_an_identical_snapshot_is_empty() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order(1, 100, TimeInForce::GoodTillCancel)).unwrap();
+
+        let mut same = BookSide::new(Side::Bid);
+        same.tree_mut().insert(order(2, 100, TimeInForce::GoodTillCancel)).unwrap();
+
+        assert!(bids.diff(&same, 0).is_empty());
+    }
+
+    fn book_for_quote_cap() -> BookSide {
+        let mut asks = BookSide::new(Side::Ask);
+        let mut a = order(1, 100, TimeInForce::GoodTillCancel);
+        a.quantity_lots = 5;
+        asks.tree_mut().insert(a).unwrap();
+        let mut b = order(2, 101, TimeInForce::GoodTillCancel);
+        b.quantity_lots = 5;
+        asks.tree_mut().insert(b).unwrap();
+        asks
+    }
+
+    #[test]
+    fn quantity_at_price_with_quote_cap_stops_at_price_limit_when_quote_cap_is_generous() {
+        let asks = book_for_quote_cap();
+        // Limit price only crosses the first level; a huge quote cap
+        // never binds.
+        assert_eq!(
+            asks.quantity_at_price_with_quote_cap(PriceLots(100), QuoteLots(1_000_000), 0),
+            BaseLots(5)
+        );
+    }
+
+    #[test]
+    fn quantity_at_price_with_quote_cap_binds_before_the_price_limit() {
+        let asks = book_for_quote_cap();
+        // The price limit would allow both levels (10 lots), but a quote
+        // budget of 350 only affords 3 lots at 100 before running out.
+        assert_eq!(
+            asks.quantity_at_price_with_quote_cap(PriceLots(101), QuoteLots(350), 0),
+            BaseLots(3)
+        );
+    }
+
+    #[test]
+    fn quantity_at_price_with_quote_cap_skips_expired_orders() {
+        let mut asks = BookSide::new(Side::Ask);
+        let mut expired = order(1, 100, TimeInForce::GoodTillTime { expiry_ts: 10 });
+        expired.quantity_lots = 5;
+        asks.tree_mut().insert(expired).unwrap();
+        let mut live = order(2, 101, TimeInForce::GoodTillCancel);
+        live.quantity_lots = 5;
+        asks.tree_mut().insert(live).unwrap();
+
+        assert_eq!(
+            asks.quantity_at_price_with_quote_cap(PriceLots(101), QuoteLots(1_000_000), 20),
+            BaseLots(5)
+        );
+    }
+
+    #[test]
+    fn quantity_at_price_with_quote_cap_bounded_matches_the_unbounded_walk_when_the_cap_is_never_hit() {
+        let asks = book_for_quote_cap();
+        let (matched, truncated) =
+            asks.quantity_at_price_with_quote_cap_bounded(PriceLots(101), QuoteLots(1_000_000), 0, 10);
+        assert_eq!(matched, BaseLots(10));
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn quantity_at_price_with_quote_cap_bounded_truncates_before_reaching_the_second_level() {
+        let asks = book_for_quote_cap();
+        // Only one order examined even though the price limit and quote
+        // cap would both allow walking into the second level.
+        let (matched, truncated) =
+            asks.quantity_at_price_with_quote_cap_bounded(PriceLots(101), QuoteLots(1_000_000), 0, 1);
+        assert_eq!(matched, BaseLots(5));
+        assert!(truncated, "hitting max_iterations must be distinguishable from a complete walk");
+    }
+
+    #[test]
+    fn quantity_at_price_with_quote_cap_bounded_handles_i64_max_price_and_quote_cap_without_panicking() {
+        let mut asks = BookSide::new(Side::Ask);
+        let mut huge = order(1, i64::MAX, TimeInForce::GoodTillCancel);
+        huge.quantity_lots = i64::MAX;
+        asks.tree_mut().insert(huge).unwrap();
+
+        // Right at the edge of what an i64 can represent: the affordable
+        // size, and the quote notional it's multiplied back into, must
+        // stay saturated instead of panicking (debug) or wrapping to a
+        // bogus, too-small answer (release).
+        let (matched, _) =
+            asks.quantity_at_price_with_quote_cap_bounded(PriceLots(i64::MAX), QuoteLots(i64::MAX), 0, 10);
+        assert_eq!(matched, BaseLots(1));
+    }
+
+    #[test]
+    fn iter_until_price_stops_at_the_limit_on_asks() {
+        let asks = book_for_quote_cap();
+        let order_ids: Vec<u128> = asks.iter_until_price(100, 0).map(|o| o.order_id).collect();
+        assert_eq!(order_ids, vec![1]);
+
+        let order_ids: Vec<u128> = asks.iter_until_price(101, 0).map(|o| o.order_id).collect();
+        assert_eq!(order_ids, vec![1, 2]);
+
+        assert!(asks.iter_until_price(99, 0).next().is_none());
+    }
+
+    #[test]
+    fn iter_until_price_stops_at_the_limit_on_bids() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order(1, 100, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(2, 99, TimeInForce::GoodTillCancel)).unwrap();
+
+        let order_ids: Vec<u128> = bids.iter_until_price(100, 0).map(|o| o.order_id).collect();
+        assert_eq!(order_ids, vec![1]);
+
+        let order_ids: Vec<u128> = bids.iter_until_price(99, 0).map(|o| o.order_id).collect();
+        assert_eq!(order_ids, vec![1, 2]);
+
+        assert!(bids.iter_until_price(101, 0).next().is_none());
+    }
+
+    #[test]
+    fn iter_until_price_skips_expired_orders() {
+        let mut asks = BookSide::new(Side::Ask);
+        asks.tree_mut()
+            .insert(order(1, 100, TimeInForce::GoodTillTime { expiry_ts: 10 }))
+            .unwrap();
+        asks.tree_mut().insert(order(2, 101, TimeInForce::GoodTillCancel)).unwrap();
+
+        let order_ids: Vec<u128> = asks.iter_until_price(101, 20).map(|o| o.order_id).collect();
+        assert_eq!(order_ids, vec![2]);
+    }
+
+    #[test]
+    fn tree_insert_never_collides_even_with_a_repeated_order_id() {
+        // `OrderTree::insert`'s key is `(price, insertion sequence)`, not
+        // `order_id`, so two orders sharing an `order_id` still get
+        // distinct handles and both leaves are present. Rejecting a
+        // duplicate `order_id` is `Orderbook::new_order`'s job, not this
+        // tree's.
+        let mut bids = BookSide::new(Side::Bid);
+        let first = bids.tree_mut().insert(order(1, 90, TimeInForce::GoodTillCancel)).unwrap();
+        let second = bids.tree_mut().insert(order(1, 95, TimeInForce::GoodTillCancel)).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(bids.tree().leaf_count(), 2);
+        assert!(bids.tree().get(first).is_some());
+        assert!(bids.tree().get(second).is_some());
+    }
+
+    #[test]
+    fn iter_owner_yields_only_that_owners_orders_best_first() {
+        let me = Pubkey::new_from_array([9; 32]);
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order_with_owner(1, 90, TimeInForce::GoodTillCancel, me)).unwrap();
+        bids.tree_mut().insert(order(2, 99, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order_with_owner(3, 95, TimeInForce::GoodTillCancel, me)).unwrap();
+
+        let mine: Vec<u128> = bids.iter_owner(&me, 0).map(|order| order.order_id).collect();
+        assert_eq!(mine, vec![3, 1]);
+    }
+
+    #[test]
+    fn iter_owner_skips_expired_orders() {
+        let me = Pubkey::new_from_array([9; 32]);
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut()
+            .insert(order_with_owner(1, 95, TimeInForce::GoodTillTime { expiry_ts: 10 }, me))
+            .unwrap();
+        bids.tree_mut().insert(order_with_owner(2, 90, TimeInForce::GoodTillCancel, me)).unwrap();
+
+        let mine: Vec<u128> = bids.iter_owner(&me, 20).map(|order| order.order_id).collect();
+        assert_eq!(mine, vec![2]);
+    }
+
+    #[test]
+    fn iter_owner_can_be_taken_without_scanning_the_whole_side() {
+        let me = Pubkey::new_from_array([9; 32]);
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order_with_owner(1, 99, TimeInForce::GoodTillCancel, me)).unwrap();
+        bids.tree_mut().insert(order(2, 90, TimeInForce::GoodTillCancel)).unwrap();
+
+        let first = bids.iter_owner(&me, 0).take(1).next().unwrap();
+        assert_eq!(first.order_id, 1);
+    }
+
+    #[test]
+    fn best_price_excluding_skips_the_owners_top_of_book_order() {
+        let me = Pubkey::new_from_array([9; 32]);
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order_with_owner(1, 100, TimeInForce::GoodTillCancel, me)).unwrap();
+        bids.tree_mut().insert(order(2, 95, TimeInForce::GoodTillCancel)).unwrap();
+
+        assert_eq!(bids.best_price(), Some(100));
+        assert_eq!(bids.best_price_excluding(&me, 0), Some(95));
+    }
+
+    #[test]
+    fn best_price_excluding_skips_expired_orders_too() {
+        let me = Pubkey::new_from_array([9; 32]);
+        let other = Pubkey::new_from_array([1; 32]);
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order_with_owner(1, 100, TimeInForce::GoodTillCancel, me)).unwrap();
+        bids.tree_mut()
+            .insert(order_with_owner(2, 99, TimeInForce::GoodTillTime { expiry_ts: 10 }, other))
+            .unwrap();
+        bids.tree_mut().insert(order_with_owner(3, 95, TimeInForce::GoodTillCancel, other)).unwrap();
+
+        assert_eq!(bids.best_price_excluding(&me, 20), Some(95));
+    }
+
+    #[test]
+    fn best_price_excluding_is_none_when_only_the_owner_has_orders() {
+        let me = Pubkey::new_from_array([9; 32]);
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order_with_owner(1, 100, TimeInForce::GoodTillCancel, me)).unwrap();
+
+        assert_eq!(bids.best_price_excluding(&me, 0), None);
+    }
+
+    #[test]
+    fn min_max_price_on_a_multi_level_bid_side() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.tree_mut().insert(order(1, 90, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(2, 100, TimeInForce::GoodTillCancel)).unwrap();
+        bids.tree_mut().insert(order(3, 95, TimeInForce::GoodTillCancel)).unwrap();
+
+        // Best price (100) is the max; worst price (90) is the min.
+        assert_eq!(bids.min_price(), Some(90));
+        assert_eq!(bids.max_price(), Some(100));
+    }
+
+    #[test]
+    fn min_max_price_on_a_multi_level_ask_side() {
+        let mut asks = BookSide::new(Side::Ask);
+        asks.tree_mut().insert(order(1, 105, TimeInForce::GoodTillCancel)).unwrap();
+        asks.tree_mut().insert(order(2, 100, TimeInForce::GoodTillCancel)).unwrap();
+        asks.tree_mut().insert(order(3, 110, TimeInForce::GoodTillCancel)).unwrap();
+
+        // Best price (100) is the min; worst price (110) is the max.
+        assert_eq!(asks.min_price(), Some(100));
+        assert_eq!(asks.max_price(), Some(110));
+    }
+
+    #[test]
+    fn min_max_price_is_none_for_an_empty_side() {
+        let bids = BookSide::new(Side::Bid);
+        assert_eq!(bids.min_price(), None);
+        assert_eq!(bids.max_price(), None);
+    }
+
+    #[test]
+    fn compact_preserves_iter_order_and_reduces_free_node_count() {
+        let mut bids = BookSide::new(Side::Bid);
+        let handles: Vec<_> = (0..5)
+            .map(|i| bids.tree_mut().insert(order(i, 100 - i as i64, TimeInForce::GoodTillCancel)).unwrap())
+            .collect();
+        // Vacate a couple of slots so the free list has room to shrink.
+        bids.tree_mut().remove_by_handle(handles[1]);
+        bids.tree_mut().remove_by_handle(handles[3]);
+
+        let before: Vec<u128> = bids.iter_all_including_invalid().map(|o| o.order_id).collect();
+        let free_before = bids.tree().free_node_count();
+
+        bids.tree_mut().try_compact().unwrap();
+
+        let after: Vec<u128> = bids.iter_all_including_invalid().map(|o| o.order_id).collect();
+        assert_eq!(before, after);
+        assert!(bids.tree().free_node_count() < free_before);
+        assert_eq!(bids.tree().free_node_count(), 0);
+    }
+
+    #[test]
+    fn remove_by_handle_removes_in_one_step_and_keeps_counts_consistent() {
+        let mut bids = BookSide::new(Side::Bid);
+        let low = bids.tree_mut().insert(order(1, 90, TimeInForce::GoodTillCancel)).unwrap();
+        let mid = bids.tree_mut().insert(order(2, 95, TimeInForce::GoodTillCancel)).unwrap();
+        let high = bids.tree_mut().insert(order(3, 100, TimeInForce::GoodTillCancel)).unwrap();
+
+        let removed = bids.remove_by_handle(mid).unwrap();
+        assert_eq!(removed.order_id, 2);
+
+        // No key search was needed: the handle alone located and removed
+        // the leaf, so the remaining leaves and their priority order are
+        // unaffected.
+        assert_eq!(bids.tree().leaf_count(), 2);
+        assert!(bids.tree().get(mid).is_none());
+        assert!(bids.tree().get(low).is_some());
+        assert!(bids.tree().get(high).is_some());
+        assert_eq!(bids.best_order().unwrap().order_id, 3);
+        assert_eq!(bids.worst_order().unwrap().order_id, 1);
+
+        // Removing the same handle again is a no-op, not a double-free.
+        assert!(bids.remove_by_handle(mid).is_none());
+        assert_eq!(bids.tree().leaf_count(), 2);
+    }
+
+    #[test]
+    fn leaf_count_matches_reachable_leaves_after_repeated_removals() {
+        // `OrderTree::leaf_count` is `self.index.len()`, and every removal
+        // path (worst-order eviction, expiry cleanup, plain cancel) goes
+        // through `remove_by_handle`, which always removes from `index`
+        // and the slab together. So unlike a hand-maintained counter next
+        // to a separate leaf structure, `leaf_count` can't drift from the
+        // number of reachable leaves here by construction — this test is
+        // a regression guard against a future refactor breaking that,
+        // not a probe of a live desync risk today.
+        let mut bids = BookSide::new(Side::Bid);
+        let mut expiring_at = 10;
+        for i in 0..20u128 {
+            let tif = if i % 3 == 0 {
+                let expiry_ts = expiring_at;
+                expiring_at += 1;
+                TimeInForce::GoodTillTime { expiry_ts }
+            } else {
+                TimeInForce::GoodTillCancel
+            };
+            bids.tree_mut().insert(order(i, 100 - i as i64, tif)).unwrap();
+        }
+        assert_eq!(bids.tree().leaf_count() as usize, bids.iter_all_including_invalid().count());
+
+        // Repeatedly evict the worst-priced order, mimicking a full-book
+        // eviction, checking the invariant after each removal.
+        for _ in 0..5 {
+            let (worst_handle, _) = bids.tree().worst().unwrap();
+            bids.remove_by_handle(worst_handle);
+            assert_eq!(bids.tree().leaf_count() as usize, bids.iter_all_including_invalid().count());
+        }
+
+        // Repeatedly remove expired orders, mimicking `remove_expired`'s
+        // one-at-a-time cleanup, checking the invariant after each.
+        let now_ts = 20;
+        while let Some((handle, _)) = bids.tree().iter().find(|(_, o)| o.is_expired(now_ts)) {
+            bids.remove_by_handle(handle);
+            assert_eq!(bids.tree().leaf_count() as usize, bids.iter_all_including_invalid().count());
+        }
+
+        assert!(bids.iter_all_including_invalid().all(|o| !o.is_expired(now_ts)));
+    }
+
+    #[test]
+    fn occupancy_and_is_full_agree_as_a_side_fills_up() {
+        let mut bids = BookSide::new(Side::Bid);
+        let capacity = bids.capacity();
+
+        for i in 0..capacity as u128 {
+            assert!(bids.occupancy() < 1.0);
+            assert!(!bids.is_full());
+            bids.tree_mut().insert(order(i, 100, TimeInForce::GoodTillCancel)).unwrap();
+        }
+
+        assert_eq!(bids.occupancy(), 1.0);
+        assert!(bids.is_full());
+    }
+
+    #[test]
+    fn inserting_below_min_price_lots_is_rejected_in_every_build_profile() {
+        // `OrderTree::insert` is the one place every resting order enters
+        // the book, so it rejects a non-positive price outright rather
+        // than relying on `node_key`'s `debug_assert`, which release
+        // builds compile out.
+        let mut bids = BookSide::new(Side::Bid);
+        assert_eq!(
+            bids.tree_mut().insert(order(1, 0, TimeInForce::GoodTillCancel)).unwrap_err(),
+            crate::error::OrderbookError::InvalidPrice
+        );
+        assert_eq!(
+            bids.tree_mut().insert(order(2, -1, TimeInForce::GoodTillCancel)).unwrap_err(),
+            crate::error::OrderbookError::InvalidPrice
+        );
+        assert!(bids.is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+//! Test-only instrumentation for the matching loop's compute footprint.
+//!
+//! Real compute-unit metering only exists on-chain, so tests instead
+//! count book-node visits and events pushed during [`super::orderbook::Orderbook::new_order`]
+//! and assert those counts stay bounded. This catches an accidental
+//! O(n^2) regression in the matching loop without needing a BPF runtime.
+
+use std::cell::Cell;
+
+thread_local! {
+    static NODE_VISITS: Cell<u64> = const { Cell::new(0) };
+    static EVENTS_PUSHED: Cell<u64> = const { Cell::new(0) };
+    static TREE_OPS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Resets all counters. Call at the start of a test.
+pub fn reset() {
+    NODE_VISITS.with(|c| c.set(0));
+    EVENTS_PUSHED.with(|c| c.set(0));
+    TREE_OPS.with(|c| c.set(0));
+}
+
+/// Number of opposing-side book nodes examined since the last [`reset`].
+pub fn node_visits() -> u64 {
+    NODE_VISITS.with(|c| c.get())
+}
+
+/// Number of `FillEvent`s the matching loop pushed to an
+/// [`super::event_queue::EventQueue`] since the last [`reset`]. `OutEvent`s
+/// are not counted.
+pub fn events_pushed() -> u64 {
+    EVENTS_PUSHED.with(|c| c.get())
+}
+
+/// Number of [`super::order_tree::OrderTree`] slab mutations (insert or
+/// remove) since the last [`reset`]. Distinct from [`node_visits`], which
+/// counts read-only scanning during matching: this counts the actual
+/// insert/evict work the "book is full, evict the worst order" hot path
+/// in [`super::orderbook::Orderbook::new_order`] does, so a test can pin
+/// how many slab operations that path costs and catch a regression that
+/// makes it, say, rescan the whole side per operation.
+pub fn tree_ops() -> u64 {
+    TREE_OPS.with(|c| c.get())
+}
+
+pub(crate) fn record_node_visit() {
+    NODE_VISITS.with(|c| c.set(c.get() + 1));
+}
+
+pub(crate) fn record_event_pushed() {
+    EVENTS_PUSHED.with(|c| c.set(c.get() + 1));
+}
+
+pub(crate) fn record_tree_op() {
+    TREE_OPS.with(|c| c.set(c.get() + 1));
+}
@@ -0,0 +1,940 @@
+//! The queue of fill/out events produced by the matching engine, consumed
+//! later by a permissionless "consume events" instruction.
+
+use {crate::error::OrderbookError, solana_program::pubkey::Pubkey};
+
+/// Discriminates the concrete event type stored in an [`AnyEvent`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum EventType {
+    Fill,
+    Out,
+}
+
+/// Emitted once per maker order matched, carrying both the maker and the
+/// taker of that match.
+///
+/// `#[repr(C)]` pins the field layout so it can only change deliberately;
+/// see the `fill_event_field_offsets_are_pinned` test, which fails the
+/// build if a field is reordered, resized, added, or removed without a
+/// matching `VERSION` bump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct FillEvent {
+    pub event_type: EventType,
+    pub taker_side: crate::state::order::Side,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey_as_base58"))]
+    pub maker: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey_as_base58"))]
+    pub taker: Pubkey,
+    pub maker_order_id: u128,
+    pub taker_order_id: u128,
+    pub price_lots: i64,
+    pub quantity_lots: i64,
+    pub maker_timestamp: i64,
+    pub timestamp: i64,
+    pub maker_strategy_id: u8,
+    pub taker_strategy_id: u8,
+}
+
+impl FillEvent {
+    /// Bumped whenever `FillEvent`'s field layout changes, so a consumer
+    /// decoding raw bytes from an older program version can detect the
+    /// mismatch instead of misreading the struct.
+    pub const VERSION: u32 = 1;
+
+    /// How long (in seconds) the maker's order had been resting on the
+    /// book when this fill occurred, guarding against underflow should
+    /// `timestamp` ever predate `maker_timestamp`.
+    pub fn maker_order_age(&self) -> u64 {
+        self.timestamp.saturating_sub(self.maker_timestamp).max(0) as u64
+    }
+
+    /// This fill's `(base, quote)` amounts converted from lots to native
+    /// token units via `market`'s lot sizes, so UI consumers don't each
+    /// have to re-derive the conversion. Saturates rather than
+    /// overflowing on a pathologically large fill.
+    pub fn native_amounts(&self, market: &crate::state::market::PerpMarket) -> (i64, i64) {
+        let base_native = self.quantity_lots.saturating_mul(market.base_lot_size);
+        let quote_native = self
+            .quantity_lots
+            .saturating_mul(self.price_lots)
+            .saturating_mul(market.quote_lot_size);
+        (base_native, quote_native)
+    }
+
+    /// The reference settlement for this fill: the native-unit base/quote
+    /// balance changes (and the fee each side pays, or is rebated, on
+    /// top) for the maker and taker leg, computed from `native_amounts`
+    /// and `market`'s fee schedule. A "consume events" crank applies
+    /// these deltas directly instead of every integrator re-deriving
+    /// which side pays quote and which pays base.
+    pub fn settle(&self, market: &crate::state::market::PerpMarket) -> FillSettlement {
+        let (base_native, quote_native) = self.native_amounts(market);
+        let taker_fee = fee_native(quote_native, market.taker_fee_bps, market.fee_rounding);
+        let maker_fee = fee_native(quote_native, market.maker_fee_bps, market.fee_rounding);
+        match self.taker_side {
+            crate::state::order::Side::Bid => FillSettlement {
+                taker_base_delta: base_native,
+                taker_quote_delta: -quote_native - taker_fee,
+                maker_base_delta: -base_native,
+                maker_quote_delta: quote_native - maker_fee,
+            },
+            crate::state::order::Side::Ask => FillSettlement {
+                taker_base_delta: -base_native,
+                taker_quote_delta: quote_native - taker_fee,
+                maker_base_delta: base_native,
+                maker_quote_delta: -quote_native - maker_fee,
+            },
+        }
+    }
+}
+
+/// `quote_native * fee_bps / 10_000`, saturating rather than overflowing
+/// on a pathologically large fill. `fee_bps` may be negative (a rebate),
+/// in which case this is negative too. `rounding` governs which way a
+/// fractional-lot remainder goes: [`FeeRounding::Truncate`](crate::state::market::FeeRounding::Truncate)
+/// always rounds toward zero, [`FeeRounding::FavorProtocol`](crate::state::market::FeeRounding::FavorProtocol)
+/// always rounds toward positive infinity, which increases a positive
+/// (taker) fee and shrinks a negative (rebate) fee's magnitude alike.
+pub(crate) fn fee_native(quote_native: i64, fee_bps: i64, rounding: crate::state::market::FeeRounding) -> i64 {
+    let numerator = quote_native.saturating_mul(fee_bps);
+    match rounding {
+        crate::state::market::FeeRounding::Truncate => numerator / 10_000,
+        crate::state::market::FeeRounding::FavorProtocol => ceil_div_by_10_000(numerator),
+    }
+}
+
+/// `numerator / 10_000`, rounded toward positive infinity. Plain
+/// truncating division already rounds a negative numerator up (toward
+/// zero, which is toward positive infinity for a negative value), so
+/// only a positive numerator with a nonzero remainder needs adjusting.
+fn ceil_div_by_10_000(numerator: i64) -> i64 {
+    if numerator > 0 && numerator % 10_000 != 0 {
+        numerator / 10_000 + 1
+    } else {
+        numerator / 10_000
+    }
+}
+
+/// The native-unit balance changes [`FillEvent::settle`] says to apply to
+/// each side of a fill. A negative delta is paid out by that party, a
+/// positive one is credited to them; a fee is folded into the relevant
+/// quote delta rather than reported separately, since a maker rebate
+/// (negative `maker_fee_bps`) is just a bigger credit, not a distinct
+/// line item.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FillSettlement {
+    pub maker_base_delta: i64,
+    pub maker_quote_delta: i64,
+    pub taker_base_delta: i64,
+    pub taker_quote_delta: i64,
+}
+
+/// Why a resting order left the book, as reported by an [`OutEvent`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum OutReason {
+    /// The owner (or a crank acting on their behalf) canceled it.
+    Cancelled,
+    /// It was removed because its time-in-force had elapsed.
+    Expired,
+    /// It was removed to make room for a new order in a full book.
+    Evicted,
+    /// It was removed because it would have self-traded against an
+    /// incoming order and the taker's self-trade behavior was
+    /// `CancelProvide`.
+    SelfTradeCancel,
+    /// It was fully consumed by one or more fills. Only emitted when the
+    /// market opts into `emit_maker_out_on_fill`, as a compact
+    /// alternative to inspecting every `FillEvent` for makers that
+    /// reached zero remaining quantity.
+    Filled,
+}
+
+/// Emitted when a resting order leaves the book through cancellation,
+/// expiry, book-full eviction or a self-trade cancel, and, when the market
+/// sets `emit_maker_out_on_fill`, when a maker is fully filled.
+///
+/// `#[repr(C)]` pins the field layout; see [`FillEvent`] and the
+/// `out_event_field_offsets_are_pinned` test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct OutEvent {
+    pub event_type: EventType,
+    pub side: crate::state::order::Side,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey_as_base58"))]
+    pub owner: Pubkey,
+    pub order_id: u128,
+    pub quantity_lots: i64,
+    pub out_reason: OutReason,
+    pub timestamp: i64,
+    pub strategy_id: u8,
+}
+
+impl OutEvent {
+    /// Bumped whenever `OutEvent`'s field layout changes; see
+    /// [`FillEvent::VERSION`].
+    pub const VERSION: u32 = 1;
+
+    /// The `(base, quote)` a canceller should unlock for the owner, in
+    /// native token units, mirroring [`Order::locked_amounts`](crate::state::order::Order::locked_amounts).
+    ///
+    /// An ask's lock is priced only by the base lot size, so this event
+    /// alone is enough to recompute it. A bid's lock also depends on the
+    /// order's `price_lots`, which this event doesn't carry — only the
+    /// resting `Order` does — so callers settling a bid must pass in the
+    /// price they had cached from placing or observing the order.
+    pub fn unlock_amounts(&self, price_lots: i64, market: &crate::state::market::PerpMarket) -> (i64, i64) {
+        match self.side {
+            crate::state::order::Side::Ask => (self.quantity_lots.saturating_mul(market.base_lot_size), 0),
+            crate::state::order::Side::Bid => (
+                0,
+                self.quantity_lots.saturating_mul(price_lots).saturating_mul(market.quote_lot_size),
+            ),
+        }
+    }
+}
+
+/// A type-erased event as stored in the [`EventQueue`]'s ring buffer.
+#[derive(Clone, Copy, Debug)]
+pub enum AnyEvent {
+    Fill(FillEvent),
+    Out(OutEvent),
+}
+
+impl AnyEvent {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            AnyEvent::Fill(_) => EventType::Fill,
+            AnyEvent::Out(_) => EventType::Out,
+        }
+    }
+}
+
+impl From<FillEvent> for AnyEvent {
+    fn from(event: FillEvent) -> Self {
+        AnyEvent::Fill(event)
+    }
+}
+
+impl From<OutEvent> for AnyEvent {
+    fn from(event: OutEvent) -> Self {
+        AnyEvent::Out(event)
+    }
+}
+
+/// A concrete event type that can be recovered from an [`AnyEvent`].
+///
+/// Lets [`AnyEvent::decode`] and the `TryFrom<AnyEvent>` impls share one
+/// implementation instead of hand-rolling a match per event struct.
+pub trait FromAnyEvent: Sized {
+    const EVENT_TYPE: EventType;
+
+    fn from_any_event(event: &AnyEvent) -> Option<&Self>;
+}
+
+impl FromAnyEvent for FillEvent {
+    const EVENT_TYPE: EventType = EventType::Fill;
+
+    fn from_any_event(event: &AnyEvent) -> Option<&Self> {
+        match event {
+            AnyEvent::Fill(fill) => Some(fill),
+            AnyEvent::Out(_) => None,
+        }
+    }
+}
+
+impl FromAnyEvent for OutEvent {
+    const EVENT_TYPE: EventType = EventType::Out;
+
+    fn from_any_event(event: &AnyEvent) -> Option<&Self> {
+        match event {
+            AnyEvent::Out(out) => Some(out),
+            AnyEvent::Fill(_) => None,
+        }
+    }
+}
+
+/// The concrete event type a failed [`AnyEvent::decode`] expected versus
+/// the one actually stored. Carries more detail than the bare
+/// [`OrderbookError::EventTypeMismatch`] it converts into, which can only
+/// be a numeric code once it crosses the `ProgramError` boundary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventTypeMismatch {
+    pub expected: EventType,
+    pub got: EventType,
+}
+
+impl From<EventTypeMismatch> for OrderbookError {
+    fn from(_: EventTypeMismatch) -> Self {
+        OrderbookError::EventTypeMismatch
+    }
+}
+
+impl AnyEvent {
+    /// Recovers a reference to the concrete event, or an
+    /// [`EventTypeMismatch`] naming both the requested and the actual
+    /// event type if this isn't one of those.
+    pub fn decode<T: FromAnyEvent>(&self) -> Result<&T, EventTypeMismatch> {
+        T::from_any_event(self).ok_or(EventTypeMismatch {
+            expected: T::EVENT_TYPE,
+            got: self.event_type(),
+        })
+    }
+}
+
+impl TryFrom<AnyEvent> for FillEvent {
+    type Error = OrderbookError;
+
+    fn try_from(event: AnyEvent) -> Result<Self, Self::Error> {
+        Ok(event.decode::<FillEvent>()?.to_owned())
+    }
+}
+
+impl TryFrom<AnyEvent> for OutEvent {
+    type Error = OrderbookError;
+
+    fn try_from(event: AnyEvent) -> Result<Self, Self::Error> {
+        Ok(event.decode::<OutEvent>()?.to_owned())
+    }
+}
+
+/// Capacity of the fixed-size event ring buffer.
+pub const EVENT_QUEUE_CAPACITY: usize = 2048;
+
+/// Fixed-capacity ring buffer of [`AnyEvent`]s, written by the matching
+/// engine and drained by the "consume events" instruction.
+pub struct EventQueue {
+    events: std::collections::VecDeque<AnyEvent>,
+    events_pushed: u64,
+}
+
+impl EventQueue {
+    pub fn new() -> Self {
+        Self {
+            events: std::collections::VecDeque::with_capacity(EVENT_QUEUE_CAPACITY),
+            events_pushed: 0,
+        }
+    }
+
+    /// Monotonically increasing count of every event ever pushed onto
+    /// this queue, including ones already popped off the front by a
+    /// consumer. A client can treat this as the market's version number:
+    /// comparing two snapshots of `seq_num()` answers "has anything
+    /// happened since I last checked?" without keeping the whole event
+    /// history around.
+    pub fn seq_num(&self) -> u64 {
+        self.events_pushed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.events.len() >= EVENT_QUEUE_CAPACITY
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn push_back(&mut self, event: AnyEvent) -> Result<(), OrderbookError> {
+        if self.is_full() {
+            return Err(OrderbookError::QueueFull);
+        }
+        self.events.push_back(event);
+        self.events_pushed += 1;
+        Ok(())
+    }
+
+    /// Test-only constructor that builds a queue already holding
+    /// `events`, in order, with `seq_num` advanced the same way pushing
+    /// them one at a time would. Cuts the boilerplate of building a
+    /// queue by hand in tests that only care about draining or iterating
+    /// it. Returns the queue in a `Box`.
+    ///
+    /// Panics if `events` is longer than `EVENT_QUEUE_CAPACITY` — a test
+    /// fixture overflowing the real queue's capacity is a bug in the
+    /// test, not something worth a `Result` here.
+    #[cfg(test)]
+    pub fn from_events(events: &[AnyEvent]) -> Box<Self> {
+        let mut queue = Box::new(Self::new());
+        for &event in events {
+            queue.push_back(event).expect("from_events fixture exceeded EVENT_QUEUE_CAPACITY");
+        }
+        queue
+    }
+
+    pub fn pop_front(&mut self) -> Result<AnyEvent, OrderbookError> {
+        self.events.pop_front().ok_or(OrderbookError::QueueEmpty)
+    }
+
+    pub fn peek_front(&self) -> Option<&AnyEvent> {
+        self.events.front()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AnyEvent> {
+        self.events.iter()
+    }
+
+    /// Iterates events involving `owner`: fills where `owner` is either
+    /// the maker or the taker, and outs belonging to `owner`. Lets a
+    /// settlement crank for a single account skip events it has no
+    /// stake in without the caller having to decode each event itself.
+    pub fn iter_for_owner<'a>(&'a self, owner: &'a Pubkey) -> impl Iterator<Item = &'a AnyEvent> {
+        self.events.iter().filter(move |event| match event {
+            AnyEvent::Fill(fill) => fill.maker == *owner || fill.taker == *owner,
+            AnyEvent::Out(out) => out.owner == *owner,
+        })
+    }
+
+    /// Pops and returns consecutive events from the front of the queue
+    /// while `f` holds, stopping (without popping) at the first event
+    /// that doesn't match. Supports "process all fills until the first
+    /// out event" style consumption.
+    pub fn retain_front_while<F: Fn(&AnyEvent) -> bool>(&mut self, f: F) -> Vec<AnyEvent> {
+        let mut drained = Vec::new();
+        while let Some(event) = self.peek_front() {
+            if !f(event) {
+                break;
+            }
+            drained.push(self.pop_front().unwrap());
+        }
+        drained
+    }
+}
+
+impl Default for EventQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::state::order::Side, solana_program::pubkey::Pubkey};
+
+    fn fill_event() -> AnyEvent {
+        FillEvent {
+            event_type: EventType::Fill,
+            taker_side: Side::Bid,
+            maker: Pubkey::new_from_array([1; 32]),
+            taker: Pubkey::new_from_array([2; 32]),
+            maker_order_id: 1,
+            taker_order_id: 2,
+            price_lots: 100,
+            quantity_lots: 10,
+            maker_timestamp: 0,
+            timestamp: 0,
+            maker_strategy_id: 0,
+            taker_strategy_id: 0,
+        }
+        .into()
+    }
+
+    fn out_event() -> AnyEvent {
+        OutEvent {
+            event_type: EventType::Out,
+            side: Side::Bid,
+            owner: Pubkey::new_from_array([1; 32]),
+            order_id: 1,
+            quantity_lots: 10,
+            out_reason: OutReason::Cancelled,
+            timestamp: 0,
+            strategy_id: 0,
+        }
+        .into()
+    }
+
+    #[test]
+    fn decode_matching_type_succeeds() {
+        let event = fill_event();
+        assert!(event.decode::<FillEvent>().is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn fill_event_json_round_trips_with_base58_pubkeys() {
+        let fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        let json = serde_json::to_value(fill).unwrap();
+        assert_eq!(json["maker"], fill.maker.to_string());
+        assert_eq!(json["taker"], fill.taker.to_string());
+        assert_eq!(serde_json::from_value::<FillEvent>(json).unwrap(), fill);
+    }
+
+    #[test]
+    fn maker_order_age_is_timestamp_minus_maker_timestamp() {
+        let mut fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        fill.maker_timestamp = 1_000;
+        fill.timestamp = 1_045;
+        assert_eq!(fill.maker_order_age(), 45);
+    }
+
+    fn market_with_lot_sizes(base_lot_size: i64, quote_lot_size: i64) -> crate::state::market::PerpMarket {
+        crate::state::market::PerpMarket {
+            admin: Pubkey::default(),
+            base_mint: Pubkey::default(),
+            quote_mint: Pubkey::default(),
+            bids: Pubkey::default(),
+            asks: Pubkey::default(),
+            event_queue: Pubkey::default(),
+            base_lot_size,
+            quote_lot_size,
+            taker_fee_bps: 0,
+            maker_fee_bps: 0,
+            max_expired_opposing_drops_per_place: 5,
+            max_expired_own_side_drops_per_place: 1,
+            mode: crate::state::market::MarketMode::Active,
+            matching_policy: crate::state::market::MatchingPolicy::PriceTime,
+            emit_maker_out_on_fill: false,
+            fee_penalty_bps: 0,
+            fee_penalty_fill_threshold_bps: 0,
+            total_orders_placed: 0,
+            total_base_lots_filled: 0,
+            fee_rounding: crate::state::market::FeeRounding::Truncate,
+            match_on_touch: true,
+            max_self_trade_cancels_per_place: 5,
+            force_self_trade_prevention: false,
+            open_interest_base_lots: 0,
+        }
+    }
+
+    #[test]
+    fn native_amounts_converts_lots_using_market_lot_sizes() {
+        let mut fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        fill.quantity_lots = 7;
+        fill.price_lots = 3;
+        let market = market_with_lot_sizes(100, 10);
+
+        // 7 base lots * 100 native/lot = 700 base native.
+        // 7 * 3 = 21 quote lots * 10 native/lot = 210 quote native.
+        assert_eq!(fill.native_amounts(&market), (700, 210));
+    }
+
+    #[test]
+    fn settle_bid_taker_pays_quote_plus_fee_and_receives_base() {
+        let mut fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        fill.taker_side = Side::Bid;
+        fill.quantity_lots = 10;
+        fill.price_lots = 100;
+        let mut market = market_with_lot_sizes(1, 1);
+        market.taker_fee_bps = 100; // 1%
+        market.maker_fee_bps = -20; // 0.2% rebate
+
+        // quote_native = 10 * 100 = 1_000; taker fee = 10, maker fee = -2.
+        assert_eq!(
+            fill.settle(&market),
+            FillSettlement {
+                taker_base_delta: 10,
+                taker_quote_delta: -1_010,
+                maker_base_delta: -10,
+                maker_quote_delta: 1_002,
+            }
+        );
+    }
+
+    #[test]
+    fn settle_ask_taker_pays_base_and_receives_quote_minus_fee() {
+        let mut fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        fill.taker_side = Side::Ask;
+        fill.quantity_lots = 10;
+        fill.price_lots = 100;
+        let mut market = market_with_lot_sizes(1, 1);
+        market.taker_fee_bps = 100;
+        market.maker_fee_bps = 0;
+
+        assert_eq!(
+            fill.settle(&market),
+            FillSettlement {
+                taker_base_delta: -10,
+                taker_quote_delta: 990,
+                maker_base_delta: 10,
+                maker_quote_delta: -1_000,
+            }
+        );
+    }
+
+    #[test]
+    fn settle_truncates_a_fractional_taker_fee_by_default() {
+        let mut fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        fill.taker_side = Side::Bid;
+        fill.quantity_lots = 1;
+        fill.price_lots = 999;
+        let mut market = market_with_lot_sizes(1, 1);
+        market.taker_fee_bps = 100; // 1% of 999 = 9.99, truncated to 9.
+
+        assert_eq!(fill.settle(&market).taker_quote_delta, -(999 + 9));
+    }
+
+    #[test]
+    fn settle_favor_protocol_rounds_a_fractional_taker_fee_up() {
+        let mut fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        fill.taker_side = Side::Bid;
+        fill.quantity_lots = 1;
+        fill.price_lots = 999;
+        let mut market = market_with_lot_sizes(1, 1);
+        market.taker_fee_bps = 100; // 1% of 999 = 9.99, rounded up to 10.
+        market.fee_rounding = crate::state::market::FeeRounding::FavorProtocol;
+
+        assert_eq!(fill.settle(&market).taker_quote_delta, -(999 + 10));
+    }
+
+    #[test]
+    fn settle_favor_protocol_still_shrinks_a_maker_rebate_toward_zero() {
+        // A rebate (negative maker_fee_bps) already rounds toward zero
+        // under plain truncation, which is the direction that favors the
+        // protocol (a smaller payout) — so `FavorProtocol` agrees with
+        // `Truncate` here rather than rounding the rebate's magnitude up.
+        let mut fill: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        fill.taker_side = Side::Bid;
+        fill.quantity_lots = 1;
+        fill.price_lots = 999;
+        let mut truncate_market = market_with_lot_sizes(1, 1);
+        truncate_market.maker_fee_bps = -100; // -1% of 999 = -9.99, truncated to -9.
+        let mut favor_protocol_market = market_with_lot_sizes(1, 1);
+        favor_protocol_market.maker_fee_bps = -100;
+        favor_protocol_market.fee_rounding = crate::state::market::FeeRounding::FavorProtocol;
+
+        let truncated = fill.settle(&truncate_market).maker_quote_delta;
+        let favor_protocol = fill.settle(&favor_protocol_market).maker_quote_delta;
+        assert_eq!(truncated, 999 - (-9));
+        assert_eq!(favor_protocol, truncated);
+    }
+
+    #[test]
+    fn unlock_amounts_for_an_ask_out_event_needs_no_price() {
+        let out: OutEvent = out_event().decode::<OutEvent>().unwrap().to_owned();
+        let mut ask_out = out;
+        ask_out.side = Side::Ask;
+        ask_out.quantity_lots = 10;
+        let market = market_with_lot_sizes(3, 1);
+
+        assert_eq!(ask_out.unlock_amounts(0, &market), (30, 0));
+    }
+
+    #[test]
+    fn unlock_amounts_for_a_bid_out_event_uses_the_passed_in_price() {
+        let mut bid_out: OutEvent = out_event().decode::<OutEvent>().unwrap().to_owned();
+        bid_out.side = Side::Bid;
+        bid_out.quantity_lots = 10;
+        let market = market_with_lot_sizes(1, 5);
+
+        assert_eq!(bid_out.unlock_amounts(100, &market), (0, 5_000));
+    }
+
+    #[test]
+    fn decode_wrong_type_is_event_type_mismatch() {
+        let event = fill_event();
+        let mismatch = event.decode::<OutEvent>().unwrap_err();
+        assert_eq!(mismatch.expected, EventType::Out);
+        assert_eq!(mismatch.got, EventType::Fill);
+        assert_eq!(OrderbookError::from(mismatch), OrderbookError::EventTypeMismatch);
+    }
+
+    #[test]
+    fn decoding_an_out_event_as_a_fill_event_reports_expected_and_got() {
+        let event = out_event();
+        let mismatch = event.decode::<FillEvent>().unwrap_err();
+        assert_eq!(mismatch.expected, EventType::Fill);
+        assert_eq!(mismatch.got, EventType::Out);
+
+        assert_eq!(FillEvent::try_from(event).unwrap_err(), OrderbookError::EventTypeMismatch);
+    }
+
+    #[test]
+    fn retain_front_while_stops_at_first_non_matching_event() {
+        let mut eq = EventQueue::new();
+        eq.push_back(fill_event()).unwrap();
+        eq.push_back(fill_event()).unwrap();
+        eq.push_back(out_event()).unwrap();
+        eq.push_back(fill_event()).unwrap();
+
+        let drained = eq.retain_front_while(|event| event.event_type() == EventType::Fill);
+
+        assert_eq!(drained.len(), 2);
+        assert!(drained.iter().all(|event| event.event_type() == EventType::Fill));
+
+        // The out event and everything after it are left in the queue,
+        // in their original order.
+        assert_eq!(eq.len(), 2);
+        assert_eq!(eq.pop_front().unwrap().event_type(), EventType::Out);
+        assert_eq!(eq.pop_front().unwrap().event_type(), EventType::Fill);
+    }
+
+    #[test]
+    fn retain_front_while_drains_whole_queue_when_all_match() {
+        let mut eq = EventQueue::new();
+        eq.push_back(fill_event()).unwrap();
+        eq.push_back(fill_event()).unwrap();
+
+        let drained = eq.retain_front_while(|_| true);
+
+        assert_eq!(drained.len(), 2);
+        assert!(eq.is_empty());
+    }
+
+    #[test]
+    fn push_back_on_a_full_queue_returns_queue_full() {
+        let mut eq = EventQueue::new();
+        while !eq.is_full() {
+            eq.push_back(fill_event()).unwrap();
+        }
+
+        assert_eq!(eq.push_back(fill_event()).unwrap_err(), OrderbookError::QueueFull);
+        // The failed push didn't touch the queue.
+        assert_eq!(eq.len(), EVENT_QUEUE_CAPACITY);
+    }
+
+    #[test]
+    fn pop_front_on_an_empty_queue_returns_queue_empty() {
+        let mut eq = EventQueue::new();
+        assert_eq!(eq.pop_front().unwrap_err(), OrderbookError::QueueEmpty);
+
+        eq.push_back(fill_event()).unwrap();
+        assert!(eq.pop_front().is_ok());
+        assert_eq!(eq.pop_front().unwrap_err(), OrderbookError::QueueEmpty);
+    }
+
+    #[test]
+    fn iter_for_owner_yields_fills_as_maker_or_taker_and_matching_outs() {
+        let maker = Pubkey::new_from_array([1; 32]);
+        let taker = Pubkey::new_from_array([2; 32]);
+        let bystander = Pubkey::new_from_array([3; 32]);
+
+        let mut eq = EventQueue::new();
+        eq.push_back(fill_event()).unwrap(); // maker = [1;32], taker = [2;32]
+        eq.push_back(out_event()).unwrap(); // owner = [1;32]
+        eq.push_back(
+            OutEvent {
+                event_type: EventType::Out,
+                side: Side::Ask,
+                owner: bystander,
+                order_id: 2,
+                quantity_lots: 5,
+                out_reason: OutReason::Cancelled,
+                timestamp: 0,
+                strategy_id: 0,
+            }
+            .into(),
+        )
+        .unwrap();
+
+        let for_maker: Vec<&AnyEvent> = eq.iter_for_owner(&maker).collect();
+        assert_eq!(for_maker.len(), 2);
+
+        let for_taker: Vec<&AnyEvent> = eq.iter_for_owner(&taker).collect();
+        assert_eq!(for_taker.len(), 1);
+        assert_eq!(for_taker[0].event_type(), EventType::Fill);
+
+        let for_bystander: Vec<&AnyEvent> = eq.iter_for_owner(&bystander).collect();
+        assert_eq!(for_bystander.len(), 1);
+        assert_eq!(for_bystander[0].event_type(), EventType::Out);
+    }
+
+    /// Field-by-field `(size, align)` of a `#[repr(C)]` struct, in
+    /// declaration order, folded into the byte offset each field lands
+    /// at under C layout rules. Recomputing this independently (rather
+    /// than hardcoding platform-specific numbers, which would differ
+    /// between the host target `cargo test` runs on and the BPF target
+    /// the program actually ships on) still catches a field being
+    /// reordered, resized, added, or removed, since the offsets below
+    /// only agree with the real struct if its declaration matches this
+    /// list exactly.
+    fn c_layout_offsets(fields: &[(usize, usize)]) -> Vec<usize> {
+        let mut offset = 0;
+        let mut offsets = Vec::with_capacity(fields.len());
+        for &(size, align) in fields {
+            offset = offset.div_ceil(align) * align;
+            offsets.push(offset);
+            offset += size;
+        }
+        offsets
+    }
+
+    #[test]
+    fn fill_event_field_offsets_are_pinned() {
+        use std::mem::{align_of, size_of};
+
+        let expected = c_layout_offsets(&[
+            (size_of::<EventType>(), align_of::<EventType>()),
+            (size_of::<Side>(), align_of::<Side>()),
+            (size_of::<Pubkey>(), align_of::<Pubkey>()),
+            (size_of::<Pubkey>(), align_of::<Pubkey>()),
+            (size_of::<u128>(), align_of::<u128>()),
+            (size_of::<u128>(), align_of::<u128>()),
+            (size_of::<i64>(), align_of::<i64>()),
+            (size_of::<i64>(), align_of::<i64>()),
+            (size_of::<i64>(), align_of::<i64>()),
+            (size_of::<i64>(), align_of::<i64>()),
+            (size_of::<u8>(), align_of::<u8>()),
+            (size_of::<u8>(), align_of::<u8>()),
+        ]);
+
+        let event: FillEvent = fill_event().decode::<FillEvent>().unwrap().to_owned();
+        let base = std::ptr::addr_of!(event) as usize;
+        let actual = vec![
+            std::ptr::addr_of!(event.event_type) as usize - base,
+            std::ptr::addr_of!(event.taker_side) as usize - base,
+            std::ptr::addr_of!(event.maker) as usize - base,
+            std::ptr::addr_of!(event.taker) as usize - base,
+            std::ptr::addr_of!(event.maker_order_id) as usize - base,
+            std::ptr::addr_of!(event.taker_order_id) as usize - base,
+            std::ptr::addr_of!(event.price_lots) as usize - base,
+            std::ptr::addr_of!(event.quantity_lots) as usize - base,
+            std::ptr::addr_of!(event.maker_timestamp) as usize - base,
+            std::ptr::addr_of!(event.timestamp) as usize - base,
+            std::ptr::addr_of!(event.maker_strategy_id) as usize - base,
+            std::ptr::addr_of!(event.taker_strategy_id) as usize - base,
+        ];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn out_event_field_offsets_are_pinned() {
+        use std::mem::{align_of, size_of};
+
+        let expected = c_layout_offsets(&[
+            (size_of::<EventType>(), align_of::<EventType>()),
+            (size_of::<Side>(), align_of::<Side>()),
+            (size_of::<Pubkey>(), align_of::<Pubkey>()),
+            (size_of::<u128>(), align_of::<u128>()),
+            (size_of::<i64>(), align_of::<i64>()),
+            (size_of::<OutReason>(), align_of::<OutReason>()),
+            (size_of::<i64>(), align_of::<i64>()),
+            (size_of::<u8>(), align_of::<u8>()),
+        ]);
+
+        let event: OutEvent = out_event().decode::<OutEvent>().unwrap().to_owned();
+        let base = std::ptr::addr_of!(event) as usize;
+        let actual = vec![
+            std::ptr::addr_of!(event.event_type) as usize - base,
+            std::ptr::addr_of!(event.side) as usize - base,
+            std::ptr::addr_of!(event.owner) as usize - base,
+            std::ptr::addr_of!(event.order_id) as usize - base,
+            std::ptr::addr_of!(event.quantity_lots) as usize - base,
+            std::ptr::addr_of!(event.out_reason) as usize - base,
+            std::ptr::addr_of!(event.timestamp) as usize - base,
+            std::ptr::addr_of!(event.strategy_id) as usize - base,
+        ];
+
+        assert_eq!(actual, expected);
+    }
+
+    fn tagged_event(tag: u128) -> AnyEvent {
+        FillEvent {
+            event_type: EventType::Fill,
+            taker_side: Side::Bid,
+            maker: Pubkey::new_from_array([1; 32]),
+            taker: Pubkey::new_from_array([2; 32]),
+            maker_order_id: 0,
+            taker_order_id: tag,
+            price_lots: 100,
+            quantity_lots: 10,
+            maker_timestamp: 0,
+            timestamp: 0,
+            maker_strategy_id: 0,
+            taker_strategy_id: 0,
+        }
+        .into()
+    }
+
+    fn tag_of(event: &AnyEvent) -> u128 {
+        event.decode::<FillEvent>().unwrap().taker_order_id
+    }
+
+    // This crate has no `revert_pushes`: `EventQueue` only ever grows from
+    // the back (`push_back`) and drains from the front (`pop_front`), so
+    // there's no "undo the last N pushes" operation to fuzz here. The
+    // "abort and discard what was pushed" semantics that would motivate
+    // one are handled a level up, by the instruction returning an `Err`
+    // and the whole transaction's account writes (including this queue)
+    // never landing — see e.g. `Orderbook::new_order`'s self-trade-cancel
+    // cap check, which relies on exactly that instead of manually undoing
+    // the events it already pushed. So this test fuzzes the two
+    // operations the queue actually has, `push_back`/`pop_front`, against
+    // a reference `VecDeque`.
+    #[test]
+    fn random_push_pop_streams_stay_consistent_with_a_vecdeque_reference() {
+        // Same tiny xorshift PRNG used elsewhere in this crate's tests,
+        // so fuzzing doesn't need a `rand` dev-dependency.
+        let seed: u64 = 0xF00D_BEEF_1234_5678;
+        let mut state = seed;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut queue = EventQueue::new();
+        let mut reference: std::collections::VecDeque<u128> = std::collections::VecDeque::new();
+        let mut next_tag: u128 = 0;
+
+        for step in 0..5_000u64 {
+            match next() % 2 {
+                0 if !queue.is_full() => {
+                    queue.push_back(tagged_event(next_tag)).unwrap();
+                    reference.push_back(next_tag);
+                    next_tag += 1;
+                }
+                _ => {
+                    let popped = queue.pop_front();
+                    let expected = reference.pop_front();
+                    assert_eq!(
+                        popped.ok().as_ref().map(tag_of),
+                        expected,
+                        "pop_front mismatch at step {step} (seed {seed:#x})"
+                    );
+                }
+            }
+
+            assert_eq!(queue.len(), reference.len(), "len mismatch at step {step} (seed {seed:#x})");
+            assert_eq!(
+                queue.peek_front().map(tag_of),
+                reference.front().copied(),
+                "peek_front mismatch at step {step} (seed {seed:#x})"
+            );
+            let queue_tags: Vec<u128> = queue.iter().map(tag_of).collect();
+            let reference_tags: Vec<u128> = reference.iter().copied().collect();
+            assert_eq!(queue_tags, reference_tags, "iter mismatch at step {step} (seed {seed:#x})");
+        }
+    }
+
+    #[test]
+    fn seq_num_advances_on_every_push_and_never_resets_on_pop() {
+        // A client polling `seq_num()` as a market version needs it to
+        // keep climbing across the placements/matches/cancels that
+        // generate these events, and to stay put rather than rewind once
+        // a consumer starts draining the queue.
+        let mut queue = EventQueue::new();
+        assert_eq!(queue.seq_num(), 0);
+
+        queue.push_back(tagged_event(1)).unwrap();
+        queue.push_back(tagged_event(2)).unwrap();
+        assert_eq!(queue.seq_num(), 2);
+
+        queue.pop_front().unwrap();
+        assert_eq!(queue.seq_num(), 2, "seq_num tracks events ever pushed, not the queue's current length");
+
+        queue.push_back(tagged_event(3)).unwrap();
+        assert_eq!(queue.seq_num(), 3);
+    }
+
+    #[test]
+    fn from_events_builds_a_queue_that_iterates_back_in_the_same_order() {
+        let events = [fill_event(), out_event(), fill_event()];
+        let queue = EventQueue::from_events(&events);
+
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.seq_num(), 3);
+        let decoded: Vec<bool> = queue.iter().map(|e| e.decode::<FillEvent>().is_ok()).collect();
+        assert_eq!(decoded, vec![true, false, true]);
+    }
+}
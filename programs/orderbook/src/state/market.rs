@@ -0,0 +1,326 @@
+//! The on-chain market configuration account.
+
+use {crate::error::OrderbookError, crate::state::order::Side, solana_program::pubkey::Pubkey};
+
+/// How a taker's quantity is allocated across resting orders that share
+/// the same price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchingPolicy {
+    /// Strict price/time priority: the earliest order at a price level
+    /// is filled in full before the next one is touched.
+    PriceTime,
+    /// At a given price level, allocate the taker's remaining quantity
+    /// proportionally across all resting orders at that price instead of
+    /// strictly by time priority.
+    ProRata,
+}
+
+/// Governs whether a market currently accepts new order placements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketMode {
+    /// Normal operation: placements and cancels are both allowed.
+    Active,
+    /// An admin action restricting new exposure; existing positions must
+    /// be unwound. Only cancels are allowed.
+    ReduceOnly,
+    /// The market is being shut down; like `ReduceOnly`, but permanent.
+    ForceClose,
+}
+
+/// How a fee amount that doesn't divide the notional evenly is rounded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeRounding {
+    /// Always round toward zero.
+    Truncate,
+    /// Always round toward positive infinity, regardless of sign. This
+    /// increases a taker's fee and shrinks a maker rebate's magnitude
+    /// alike, so a boundary case never costs the protocol revenue rather
+    /// than merely being "the trader's favor" half the time.
+    FavorProtocol,
+}
+
+/// Configuration and account pointers for one market's order book.
+pub struct PerpMarket {
+    pub admin: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub event_queue: Pubkey,
+    /// Smallest tradeable increment of the base token, in native units.
+    pub base_lot_size: i64,
+    /// Smallest tradeable increment of the quote token, in native units.
+    pub quote_lot_size: i64,
+    /// Taker fee, in basis points.
+    pub taker_fee_bps: i64,
+    /// Maker fee, in basis points (may be negative, i.e. a rebate).
+    pub maker_fee_bps: i64,
+    /// Maximum number of expired orders `new_order` will drop from the
+    /// opposing side while matching before giving up and treating the
+    /// book as exhausted, bounding the compute a single place can burn.
+    pub max_expired_opposing_drops_per_place: u8,
+    /// Maximum number of expired orders `new_order` will drop from its
+    /// own side to make room before posting the remainder of an order.
+    pub max_expired_own_side_drops_per_place: u8,
+    pub mode: MarketMode,
+    pub matching_policy: MatchingPolicy,
+    /// When set, `new_order` also pushes a lightweight [`OutEvent`](crate::state::event_queue::OutEvent)
+    /// (with [`OutReason::Filled`](crate::state::event_queue::OutReason::Filled))
+    /// whenever a maker is fully consumed by a fill, so a maker-settlement
+    /// crank can process only Out events instead of inspecting every
+    /// fill. Off by default to avoid the extra queue pressure.
+    pub emit_maker_out_on_fill: bool,
+    /// Extra taker fee, in basis points, charged on top of `taker_fee_bps`
+    /// for an `ImmediateOrCancel` order that fills less than
+    /// `fee_penalty_fill_threshold_bps` of its requested quantity — a
+    /// barely-filled ioc mostly burned matching-engine work rather than
+    /// trading. Zero disables the penalty.
+    pub fee_penalty_bps: i64,
+    /// Fraction of an `ImmediateOrCancel` order's `max_base_lots`, in
+    /// basis points, below which `fee_penalty_bps` applies. See
+    /// [`PerpMarket::ioc_taker_fee_bps`].
+    pub fee_penalty_fill_threshold_bps: i64,
+    /// Cumulative count of orders accepted by [`Orderbook::new_order`](crate::state::orderbook::Orderbook::new_order),
+    /// including ones that don't end up resting (Market/IOC). Not
+    /// decremented by cancels or fills; a pure lifetime counter for
+    /// analytics.
+    pub total_orders_placed: u64,
+    /// Cumulative base lots matched across all fills this order caused,
+    /// counted once per fill regardless of which side initiated it.
+    /// Cancelling a resting order never touches this counter.
+    pub total_base_lots_filled: u64,
+    /// How [`FillEvent::settle`](crate::state::event_queue::FillEvent::settle)
+    /// rounds a fee amount that doesn't divide the fill's notional evenly.
+    pub fee_rounding: FeeRounding,
+    /// Whether an incoming order priced exactly at the opposing best
+    /// (touching, rather than crossing through, the spread) matches
+    /// against it. `false` treats an exact touch as merely posting
+    /// alongside the opposing top of book instead.
+    pub match_on_touch: bool,
+    /// Maximum number of the taker's own resting orders that
+    /// `SelfTradeBehavior::CancelProvide` will cancel while placing a
+    /// single order, bounding the compute one place can burn on
+    /// self-trade cleanup the same way `max_expired_opposing_drops_per_place`
+    /// bounds expired-order cleanup. Once the cap is hit, a `Market` or
+    /// `ImmediateOrCancel` order leaves any further self-crossing makers
+    /// resting, and an order that would rest is rejected with
+    /// `SelfTradeCancelCapExceeded` if one of them still crosses its price.
+    pub max_self_trade_cancels_per_place: u8,
+    /// When set, every order placed against this market is treated as
+    /// `SelfTradeBehavior::AbortTransaction` on a self-match, regardless
+    /// of what the order itself requests. Lets an integrator enforce a
+    /// market-wide anti-wash-trading policy that individual clients
+    /// can't opt out of by choosing a softer behavior.
+    pub force_self_trade_prevention: bool,
+    /// Net open contracts across the market, in base lots. This crate's
+    /// matching engine has no notion of a position — it only resolves
+    /// order flow into fills — so nothing here updates this field; it's
+    /// maintained by whatever margin/position accounting sits on top of
+    /// the order book, and exposed here purely so [`open_interest_quote`](Self::open_interest_quote)
+    /// has something to convert.
+    pub open_interest_base_lots: i64,
+}
+
+impl PerpMarket {
+    pub fn is_reduce_only(&self) -> bool {
+        self.mode == MarketMode::ReduceOnly
+    }
+
+    pub fn is_force_close(&self) -> bool {
+        self.mode == MarketMode::ForceClose
+    }
+
+    /// The taker fee, in basis points, that should be charged for an
+    /// `ImmediateOrCancel` order that requested `max_base_lots` and
+    /// filled `filled_base_lots`. Adds `fee_penalty_bps` on top of
+    /// `taker_fee_bps` when the fill fraction is below
+    /// `fee_penalty_fill_threshold_bps`; a fully or mostly filled order
+    /// pays the plain `taker_fee_bps`.
+    pub fn ioc_taker_fee_bps(&self, max_base_lots: i64, filled_base_lots: i64) -> i64 {
+        if max_base_lots <= 0 {
+            return self.taker_fee_bps;
+        }
+        let filled_bps = filled_base_lots.saturating_mul(10_000) / max_base_lots;
+        if filled_bps < self.fee_penalty_fill_threshold_bps {
+            self.taker_fee_bps + self.fee_penalty_bps
+        } else {
+            self.taker_fee_bps
+        }
+    }
+
+    /// `open_interest_base_lots` valued at `mark_price_lots`, in quote
+    /// lots. Saturates on overflow rather than panicking, the same
+    /// convention [`Order::locked_amounts`](super::order::Order::locked_amounts)
+    /// uses for other lot-quantity conversions.
+    pub fn open_interest_quote(&self, mark_price_lots: i64) -> i64 {
+        self.open_interest_base_lots.saturating_mul(mark_price_lots)
+    }
+
+    /// Checks `price_lots` against an absolute band around
+    /// `oracle_price_lots`, before an order is ever placed.
+    ///
+    /// This is the same deviation math [`Orderbook::new_order`](crate::state::orderbook::Orderbook::new_order)
+    /// applies mid-match via `OrderParams::reference_price_lots` /
+    /// `max_price_deviation_bps` to stop a `Market`/`ImmediateOrCancel`
+    /// order from sweeping into a stale book — but that guard only
+    /// truncates the fill once matching is already underway. An
+    /// integrator that wants to reject an obviously bad order up front,
+    /// for any order type, calls this first instead.
+    pub fn check_order_price_band(
+        &self,
+        side: Side,
+        price_lots: i64,
+        oracle_price_lots: i64,
+        max_price_deviation_bps: i64,
+    ) -> Result<(), OrderbookError> {
+        let deviation = oracle_price_lots.saturating_mul(max_price_deviation_bps) / 10_000;
+        let limit = match side {
+            Side::Bid => oracle_price_lots.saturating_add(deviation),
+            Side::Ask => oracle_price_lots.saturating_sub(deviation),
+        };
+        let breaches_band = match side {
+            Side::Bid => price_lots > limit,
+            Side::Ask => price_lots < limit,
+        };
+        if breaches_band {
+            return Err(OrderbookError::SpotPriceBandExceeded);
+        }
+        Ok(())
+    }
+
+    /// Checks the market's configuration for internal consistency.
+    /// Intended to be called once, when a market account is first
+    /// initialized.
+    ///
+    /// `base_lot_size` and `quote_lot_size` must be positive powers of
+    /// 10: positive so every native-unit conversion (e.g.
+    /// [`Order::locked_amounts`](super::order::Order::locked_amounts))
+    /// is well-defined instead of dividing by zero, and a power of 10 so
+    /// lot boundaries line up with how the token's native units are
+    /// conventionally displayed.
+    pub fn validate(&self) -> Result<(), OrderbookError> {
+        if !is_positive_power_of_ten(self.base_lot_size) || !is_positive_power_of_ten(self.quote_lot_size) {
+            return Err(OrderbookError::InvalidLotSize);
+        }
+        Ok(())
+    }
+}
+
+fn is_positive_power_of_ten(value: i64) -> bool {
+    if value <= 0 {
+        return false;
+    }
+    let mut remaining = value;
+    while remaining % 10 == 0 {
+        remaining /= 10;
+    }
+    remaining == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market_with_lot_sizes(base_lot_size: i64, quote_lot_size: i64) -> PerpMarket {
+        PerpMarket {
+            admin: Pubkey::default(),
+            base_mint: Pubkey::default(),
+            quote_mint: Pubkey::default(),
+            bids: Pubkey::default(),
+            asks: Pubkey::default(),
+            event_queue: Pubkey::default(),
+            base_lot_size,
+            quote_lot_size,
+            taker_fee_bps: 0,
+            maker_fee_bps: 0,
+            max_expired_opposing_drops_per_place: 0,
+            max_expired_own_side_drops_per_place: 0,
+            mode: MarketMode::Active,
+            matching_policy: MatchingPolicy::PriceTime,
+            emit_maker_out_on_fill: false,
+            fee_penalty_bps: 0,
+            fee_penalty_fill_threshold_bps: 0,
+            total_orders_placed: 0,
+            total_base_lots_filled: 0,
+            fee_rounding: crate::state::market::FeeRounding::Truncate,
+            match_on_touch: true,
+            max_self_trade_cancels_per_place: 0,
+            force_self_trade_prevention: false,
+            open_interest_base_lots: 0,
+        }
+    }
+
+    #[test]
+    fn validate_accepts_powers_of_ten() {
+        assert!(market_with_lot_sizes(1, 1).validate().is_ok());
+        assert!(market_with_lot_sizes(100, 10_000).validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_zero_lot_sizes() {
+        assert_eq!(
+            market_with_lot_sizes(0, 1).validate().unwrap_err(),
+            OrderbookError::InvalidLotSize
+        );
+        assert_eq!(
+            market_with_lot_sizes(1, 0).validate().unwrap_err(),
+            OrderbookError::InvalidLotSize
+        );
+    }
+
+    #[test]
+    fn validate_rejects_non_power_of_ten_lot_sizes() {
+        assert_eq!(
+            market_with_lot_sizes(3, 1).validate().unwrap_err(),
+            OrderbookError::InvalidLotSize
+        );
+        assert_eq!(
+            market_with_lot_sizes(1, 250).validate().unwrap_err(),
+            OrderbookError::InvalidLotSize
+        );
+    }
+
+    #[test]
+    fn validate_rejects_negative_lot_sizes() {
+        assert_eq!(
+            market_with_lot_sizes(-100, 1).validate().unwrap_err(),
+            OrderbookError::InvalidLotSize
+        );
+    }
+
+    #[test]
+    fn open_interest_quote_multiplies_by_mark_price() {
+        let mut market = market_with_lot_sizes(1, 1);
+        market.open_interest_base_lots = 1_000;
+        assert_eq!(market.open_interest_quote(50), 50_000);
+        assert_eq!(market.open_interest_quote(0), 0);
+    }
+
+    #[test]
+    fn open_interest_quote_saturates_instead_of_overflowing() {
+        let mut market = market_with_lot_sizes(1, 1);
+        market.open_interest_base_lots = i64::MAX;
+        assert_eq!(market.open_interest_quote(2), i64::MAX);
+    }
+
+    #[test]
+    fn check_order_price_band_accepts_prices_at_and_inside_the_band_edge() {
+        let market = market_with_lot_sizes(1, 1);
+        // 100 bps of an oracle price of 1_000 is a deviation of 10.
+        assert!(market.check_order_price_band(Side::Bid, 1_010, 1_000, 100).is_ok());
+        assert!(market.check_order_price_band(Side::Ask, 990, 1_000, 100).is_ok());
+    }
+
+    #[test]
+    fn check_order_price_band_rejects_prices_beyond_the_band_edge() {
+        let market = market_with_lot_sizes(1, 1);
+        assert_eq!(
+            market.check_order_price_band(Side::Bid, 1_011, 1_000, 100).unwrap_err(),
+            OrderbookError::SpotPriceBandExceeded
+        );
+        assert_eq!(
+            market.check_order_price_band(Side::Ask, 989, 1_000, 100).unwrap_err(),
+            OrderbookError::SpotPriceBandExceeded
+        );
+    }
+}
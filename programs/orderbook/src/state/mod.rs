@@ -0,0 +1,19 @@
+pub mod book_side;
+#[cfg(test)]
+pub mod compute_counter;
+pub mod event_queue;
+pub mod market;
+pub mod order;
+pub mod order_tree;
+pub mod orderbook;
+pub mod units;
+
+pub use {
+    book_side::{BookSide, LevelChange},
+    event_queue::{AnyEvent, EventQueue, EventType, EventTypeMismatch, FillEvent, FillSettlement, OutEvent},
+    market::{FeeRounding, MarketMode, MatchingPolicy, PerpMarket},
+    order::{Order, OrderParams, OrderParamsBuilder, OrderType, RestingPart, Side, TakenPart, TimeInForce},
+    order_tree::OrderTree,
+    orderbook::{BookSummary, Orderbook, OrderResult, Ticker, MIN_PRICE_LOTS},
+    units::{BaseLots, PriceLots, QuoteLots},
+};
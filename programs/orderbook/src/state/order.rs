@@ -0,0 +1,762 @@
+//! Order types shared between the instruction payloads and the in-book
+//! representation used by the matching engine.
+
+use {
+    borsh::{BorshDeserialize, BorshSerialize},
+    num_enum::{IntoPrimitive, TryFromPrimitive},
+};
+
+/// Which side of the book an order rests on or trades against.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, BorshSerialize, BorshDeserialize,
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+impl Side {
+    /// The side an incoming order of this side would be matched against.
+    pub fn invert_side(&self) -> Side {
+        match self {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+
+    /// Whether an order of `taker_side` at `taker_price` would match
+    /// against a resting order at `maker_price`: a bid crosses at or
+    /// above the maker's ask price, an ask crosses at or below the
+    /// maker's bid price.
+    pub fn would_cross(taker_side: Side, taker_price: i64, maker_price: i64) -> bool {
+        match taker_side {
+            Side::Bid => taker_price >= maker_price,
+            Side::Ask => taker_price <= maker_price,
+        }
+    }
+
+    /// A compact, lowercase form of this side, suitable for logs or FFI
+    /// that don't want to carry the numeric repr around.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Bid => "bid",
+            Side::Ask => "ask",
+        }
+    }
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How an order behaves once it reaches the top of the book.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, BorshSerialize, BorshDeserialize,
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum OrderType {
+    /// Rests on the book until filled, canceled or expired.
+    Limit,
+    /// Rejected instead of matching immediately against the book.
+    PostOnly,
+    /// Matches against the book up to `max_base_lots`/`max_quote_lots`,
+    /// any unmatched quantity is discarded rather than posted.
+    Market,
+    /// Like `PostOnly`, but instead of being rejected when it would cross
+    /// the book, the price is adjusted ("slid") to the best non-crossing
+    /// price.
+    PostOnlySlide,
+    /// Matches immediately and cancels any unmatched quantity instead of
+    /// resting on the book.
+    ImmediateOrCancel,
+}
+
+impl OrderType {
+    /// A compact, lowercase form of this order type, suitable for logs
+    /// or FFI that don't want to carry the numeric repr around.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Limit => "limit",
+            OrderType::PostOnly => "post_only",
+            OrderType::Market => "market",
+            OrderType::PostOnlySlide => "post_only_slide",
+            OrderType::ImmediateOrCancel => "immediate_or_cancel",
+        }
+    }
+}
+
+impl std::fmt::Display for OrderType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How long an order remains eligible to rest on the book.
+///
+/// `GoodTillTime`'s `expiry_ts` is an `i64`, not narrowed to whole
+/// seconds at the type level: [`Order::is_expired`] and every `now_ts`
+/// parameter in this crate are plain integer comparisons against it, so
+/// nothing internally requires `now_ts` to be Unix seconds specifically.
+/// A market that consistently threads millisecond (or any other
+/// resolution) timestamps through every `now_ts` argument it passes —
+/// `new_order`, `remove_expired`, `cancel_expired_orders`, and so on —
+/// gets that same resolution of TIF for free. The crate's own
+/// convention, and what every doc comment elsewhere assumes, is Unix
+/// seconds; mixing resolutions within one market's calls would silently
+/// misfire, so this is an all-or-nothing choice made once per market,
+/// not a per-order flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeInForce {
+    /// Rests until explicitly canceled.
+    GoodTillCancel,
+    /// Rests until `expiry_ts` (unix timestamp, seconds by convention —
+    /// see the enum-level doc comment) is reached.
+    GoodTillTime { expiry_ts: i64 },
+}
+
+impl TimeInForce {
+    /// Returns the expiry timestamp, if any.
+    pub fn expiry_ts(&self) -> Option<i64> {
+        match self {
+            TimeInForce::GoodTillCancel => None,
+            TimeInForce::GoodTillTime { expiry_ts } => Some(*expiry_ts),
+        }
+    }
+}
+
+/// What happens when an incoming order would match against an order
+/// resting from the same owner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelfTradeBehavior {
+    /// Decrement both the resting and incoming order's quantity, as if a
+    /// real trade had taken place between them.
+    DecrementTake,
+    /// Cancel the resting order and continue matching the incoming order.
+    CancelProvide,
+    /// Fail the whole transaction.
+    AbortTransaction,
+}
+
+impl SelfTradeBehavior {
+    /// A compact, lowercase form of this behavior, suitable for logs or
+    /// FFI that don't want to carry the numeric repr around.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelfTradeBehavior::DecrementTake => "decrement_take",
+            SelfTradeBehavior::CancelProvide => "cancel_provide",
+            SelfTradeBehavior::AbortTransaction => "abort_transaction",
+        }
+    }
+}
+
+impl std::fmt::Display for SelfTradeBehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An order resting in (or about to be posted to) the book.
+///
+/// Field order is part of the Borsh wire format: it must not change
+/// without a corresponding version bump wherever `Order` is persisted or
+/// sent in an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Order {
+    /// Caller-supplied id, unique across both sides of the book, that
+    /// clients use to reference the order later. Priority comes from
+    /// price and insertion order, not from this id.
+    pub order_id: u128,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pubkey_as_base58"))]
+    pub owner: solana_program::pubkey::Pubkey,
+    pub side: Side,
+    pub price_lots: i64,
+    pub quantity_lots: i64,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    /// Unix timestamp (seconds) the order was placed at.
+    pub timestamp: i64,
+    /// Client-supplied id, echoed back in events but not used for
+    /// matching.
+    pub client_order_id: u64,
+    /// Client-supplied tag identifying which strategy placed this order,
+    /// echoed back on the fill/out events it generates so integrators
+    /// running multiple strategies through one account can attribute
+    /// them without a client-order-id lookup table. Not used for
+    /// matching.
+    pub strategy_id: u8,
+}
+
+impl Order {
+    /// Whether this order's time-in-force has elapsed as of `now_ts`.
+    /// Always `false` for `GoodTillCancel` orders.
+    pub fn is_expired(&self, now_ts: i64) -> bool {
+        self.time_in_force
+            .expiry_ts()
+            .is_some_and(|expiry_ts| now_ts >= expiry_ts)
+    }
+
+    /// Seconds remaining until this order expires, as of `now_ts`.
+    ///
+    /// `None` for orders with no time-in-force expiry. `Some(0)` if the
+    /// order has already expired, rather than `None`, so callers can
+    /// distinguish "never expires" from "expired".
+    pub fn seconds_to_expiry(&self, now_ts: i64) -> Option<u64> {
+        let expiry_ts = self.time_in_force.expiry_ts()?;
+        Some(expiry_ts.saturating_sub(now_ts).max(0) as u64)
+    }
+
+    /// The collateral this resting order locks, in native token units,
+    /// as `(base, quote)`: an ask locks the base it's obligated to
+    /// deliver, a bid locks the quote it's obligated to pay
+    /// (`quantity_lots * price_lots`). A canceller uses this to know how
+    /// much to credit back to the owner. Saturates rather than
+    /// overflowing on a pathologically large order.
+    pub fn locked_amounts(&self, market: &crate::state::market::PerpMarket) -> (i64, i64) {
+        match self.side {
+            Side::Ask => (self.quantity_lots.saturating_mul(market.base_lot_size), 0),
+            Side::Bid => (
+                0,
+                self.quantity_lots
+                    .saturating_mul(self.price_lots)
+                    .saturating_mul(market.quote_lot_size),
+            ),
+        }
+    }
+
+    /// Splits this resting order into the part a hypothetical fill of
+    /// `filled_base` base lots (for `fill_quote` quote lots) would take,
+    /// and the part that would still be resting afterward. `filled_base`
+    /// is clamped to `quantity_lots` so a caller can't over-fill the
+    /// order.
+    ///
+    /// Lets an off-chain simulator reason about the outcome of a fill
+    /// against this order without a full book.
+    pub fn split_at_fill(&self, filled_base: i64, fill_quote: i64) -> (TakenPart, RestingPart) {
+        let taken_base_lots = filled_base.clamp(0, self.quantity_lots);
+        (
+            TakenPart {
+                base_lots: taken_base_lots,
+                quote_lots: fill_quote,
+            },
+            RestingPart {
+                quantity_lots: self.quantity_lots - taken_base_lots,
+            },
+        )
+    }
+
+    /// The taker fee a fill of `filled_base_lots` at `avg_price_lots`
+    /// would incur, in native quote units, mirroring exactly the fee
+    /// math [`FillEvent::settle`](crate::state::event_queue::FillEvent::settle)
+    /// applies to a real fill. Lets a client display expected costs
+    /// before placing an order.
+    pub fn estimate_fees(&self, market: &crate::state::market::PerpMarket, filled_base_lots: i64, avg_price_lots: i64) -> i64 {
+        let quote_native = filled_base_lots
+            .saturating_mul(avg_price_lots)
+            .saturating_mul(market.quote_lot_size);
+        crate::state::event_queue::fee_native(quote_native, market.taker_fee_bps, market.fee_rounding)
+    }
+}
+
+/// The portion of an [`Order`] consumed by a fill, as returned by
+/// [`Order::split_at_fill`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TakenPart {
+    pub base_lots: i64,
+    pub quote_lots: i64,
+}
+
+/// The portion of an [`Order`] still resting after a fill, as returned
+/// by [`Order::split_at_fill`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestingPart {
+    pub quantity_lots: i64,
+}
+
+/// Parameters supplied by the client when placing a new order.
+///
+/// This is the instruction-side payload; [`Order`] is the in-book
+/// representation produced from it once the order (or its remainder)
+/// rests on the book.
+///
+/// Field order is part of the Borsh wire format; see [`Order`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct OrderParams {
+    pub side: Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots: i64,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    pub client_order_id: u64,
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub strategy_id: u8,
+    /// Marks this order as intended only to reduce an existing position.
+    /// The matching engine doesn't track positions, so it can't reject an
+    /// order that would flip one from short to long or vice versa; that
+    /// check belongs to the position-aware caller of
+    /// [`Orderbook::new_order`](crate::state::orderbook::Orderbook::new_order),
+    /// before this order ever reaches the book. Within the matching
+    /// engine itself, `reduce_only` has no effect: in particular, a
+    /// `PostOnlySlide` order still slides and posts the same way
+    /// regardless of this flag, since sliding never causes a match.
+    pub reduce_only: bool,
+    /// An external reference price (e.g. an oracle price), in price
+    /// lots, that immediate fills are checked against. `None` disables
+    /// the guard entirely. Only consulted for `Market` and
+    /// `ImmediateOrCancel` orders, since resting order types never fill
+    /// against a stale book at placement time.
+    pub reference_price_lots: Option<i64>,
+    /// How far, in basis points, a fill price may deviate from
+    /// `reference_price_lots` before it's refused: a buy won't fill
+    /// above `reference_price_lots * (1 + max_price_deviation_bps / 10_000)`,
+    /// a sell won't fill below the mirrored bound. This is an absolute
+    /// band against a trusted reference, distinct from a per-fill
+    /// slippage limit against the order's own limit price. Ignored when
+    /// `reference_price_lots` is `None`.
+    pub max_price_deviation_bps: i64,
+}
+
+impl OrderParams {
+    /// The worst-case collateral this order could lock, in native token
+    /// units, as `(base, quote)`, computed before the order is ever
+    /// placed. Mirrors [`Order::locked_amounts`], but works from the
+    /// pre-placement `max_base_lots`/`max_quote_lots` request instead of
+    /// a resting order's `quantity_lots`.
+    ///
+    /// An ask's worst case is simply its full requested base quantity.
+    /// A bid's worst case is bounded by whichever of `max_base_lots` or
+    /// `max_quote_lots` is more restrictive at the order's price:
+    /// `price_lots` for a resting order type, or `oracle_price_lots` for
+    /// a `Market` order, which has no limit price of its own. Saturates
+    /// rather than overflowing on a pathologically large order.
+    pub fn required_lock(&self, market: &crate::state::market::PerpMarket, oracle_price_lots: i64) -> (i64, i64) {
+        match self.side {
+            Side::Ask => (self.max_base_lots.saturating_mul(market.base_lot_size), 0),
+            Side::Bid => {
+                let price_lots = match self.order_type {
+                    OrderType::Market => oracle_price_lots,
+                    _ => self.price_lots,
+                };
+                let quote_lots = self.max_base_lots.saturating_mul(price_lots).min(self.max_quote_lots);
+                (0, quote_lots.saturating_mul(market.quote_lot_size))
+            }
+        }
+    }
+}
+
+/// Fluent builder for [`OrderParams`], to cut down on the boilerplate of
+/// repeating every field at each call site. Defaults to a `Limit`,
+/// `GoodTillCancel` order with [`SelfTradeBehavior::DecrementTake`], no
+/// quote-lot cap, and `reduce_only: false` — the common case for tests
+/// and simple integrations.
+pub struct OrderParamsBuilder {
+    params: OrderParams,
+}
+
+impl OrderParamsBuilder {
+    pub fn new(side: Side, price_lots: i64, max_base_lots: i64) -> Self {
+        Self {
+            params: OrderParams {
+                side,
+                price_lots,
+                max_base_lots,
+                max_quote_lots: i64::MAX,
+                order_type: OrderType::Limit,
+                time_in_force: TimeInForce::GoodTillCancel,
+                client_order_id: 0,
+                self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                strategy_id: 0,
+                reduce_only: false,
+                reference_price_lots: None,
+                max_price_deviation_bps: 0,
+            },
+        }
+    }
+
+    pub fn reference_price_guard(mut self, reference_price_lots: i64, max_price_deviation_bps: i64) -> Self {
+        self.params.reference_price_lots = Some(reference_price_lots);
+        self.params.max_price_deviation_bps = max_price_deviation_bps;
+        self
+    }
+
+    pub fn max_quote_lots(mut self, max_quote_lots: i64) -> Self {
+        self.params.max_quote_lots = max_quote_lots;
+        self
+    }
+
+    pub fn order_type(mut self, order_type: OrderType) -> Self {
+        self.params.order_type = order_type;
+        self
+    }
+
+    pub fn time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.params.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn client_order_id(mut self, client_order_id: u64) -> Self {
+        self.params.client_order_id = client_order_id;
+        self
+    }
+
+    pub fn self_trade_behavior(mut self, self_trade_behavior: SelfTradeBehavior) -> Self {
+        self.params.self_trade_behavior = self_trade_behavior;
+        self
+    }
+
+    pub fn strategy_id(mut self, strategy_id: u8) -> Self {
+        self.params.strategy_id = strategy_id;
+        self
+    }
+
+    pub fn reduce_only(mut self, reduce_only: bool) -> Self {
+        self.params.reduce_only = reduce_only;
+        self
+    }
+
+    pub fn build(self) -> OrderParams {
+        self.params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn would_cross_bid_crosses_at_or_above_maker_price() {
+        assert!(Side::would_cross(Side::Bid, 101, 100));
+        assert!(Side::would_cross(Side::Bid, 100, 100));
+        assert!(!Side::would_cross(Side::Bid, 99, 100));
+    }
+
+    #[test]
+    fn would_cross_ask_crosses_at_or_below_maker_price() {
+        assert!(Side::would_cross(Side::Ask, 99, 100));
+        assert!(Side::would_cross(Side::Ask, 100, 100));
+        assert!(!Side::would_cross(Side::Ask, 101, 100));
+    }
+
+    #[test]
+    fn side_as_str_and_display_agree() {
+        assert_eq!(Side::Bid.as_str(), "bid");
+        assert_eq!(Side::Ask.as_str(), "ask");
+        assert_eq!(Side::Bid.to_string(), "bid");
+        assert_eq!(Side::Ask.to_string(), "ask");
+    }
+
+    #[test]
+    fn order_type_as_str_and_display_agree() {
+        assert_eq!(OrderType::Limit.as_str(), "limit");
+        assert_eq!(OrderType::PostOnly.as_str(), "post_only");
+        assert_eq!(OrderType::Market.as_str(), "market");
+        assert_eq!(OrderType::PostOnlySlide.as_str(), "post_only_slide");
+        assert_eq!(OrderType::ImmediateOrCancel.as_str(), "immediate_or_cancel");
+        assert_eq!(OrderType::PostOnlySlide.to_string(), "post_only_slide");
+    }
+
+    #[test]
+    fn self_trade_behavior_as_str_and_display_agree() {
+        assert_eq!(SelfTradeBehavior::DecrementTake.as_str(), "decrement_take");
+        assert_eq!(SelfTradeBehavior::CancelProvide.as_str(), "cancel_provide");
+        assert_eq!(SelfTradeBehavior::AbortTransaction.as_str(), "abort_transaction");
+        assert_eq!(SelfTradeBehavior::CancelProvide.to_string(), "cancel_provide");
+    }
+
+    fn order_with_tif(time_in_force: TimeInForce) -> Order {
+        Order {
+            order_id: 1,
+            owner: solana_program::pubkey::Pubkey::new_from_array([7; 32]),
+            side: Side::Bid,
+            price_lots: 100,
+            quantity_lots: 10,
+            order_type: OrderType::Limit,
+            time_in_force,
+            timestamp: 1_700_000_000,
+            client_order_id: 99,
+            strategy_id: 0,
+        }
+    }
+
+    #[test]
+    fn order_round_trips_good_till_cancel() {
+        let order = order_with_tif(TimeInForce::GoodTillCancel);
+        let bytes = borsh::to_vec(&order).unwrap();
+        assert_eq!(Order::try_from_slice(&bytes).unwrap(), order);
+    }
+
+    #[test]
+    fn order_round_trips_good_till_time() {
+        let order = order_with_tif(TimeInForce::GoodTillTime { expiry_ts: 1_700_003_600 });
+        let bytes = borsh::to_vec(&order).unwrap();
+        assert_eq!(Order::try_from_slice(&bytes).unwrap(), order);
+    }
+
+    #[test]
+    fn seconds_to_expiry_is_none_for_good_till_cancel() {
+        let order = order_with_tif(TimeInForce::GoodTillCancel);
+        assert_eq!(order.seconds_to_expiry(0), None);
+    }
+
+    #[test]
+    fn seconds_to_expiry_counts_down_before_expiry() {
+        let order = order_with_tif(TimeInForce::GoodTillTime { expiry_ts: 100 });
+        assert_eq!(order.seconds_to_expiry(40), Some(60));
+    }
+
+    #[test]
+    fn seconds_to_expiry_is_zero_at_the_exact_boundary() {
+        let order = order_with_tif(TimeInForce::GoodTillTime { expiry_ts: 100 });
+        assert_eq!(order.seconds_to_expiry(100), Some(0));
+    }
+
+    #[test]
+    fn seconds_to_expiry_is_zero_once_already_expired() {
+        let order = order_with_tif(TimeInForce::GoodTillTime { expiry_ts: 100 });
+        assert_eq!(order.seconds_to_expiry(150), Some(0));
+    }
+
+    #[test]
+    fn is_expired_supports_sub_second_resolution_when_now_ts_is_also_in_milliseconds() {
+        // `expiry_ts` and `now_ts` are just integers compared against
+        // each other; a caller that consistently passes milliseconds
+        // gets sub-second expiry granularity without any code change.
+        let order = order_with_tif(TimeInForce::GoodTillTime { expiry_ts: 1_500 });
+        assert!(!order.is_expired(1_499));
+        assert!(order.is_expired(1_500));
+        assert!(order.is_expired(1_501));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn order_json_round_trips_with_base58_owner() {
+        let order = order_with_tif(TimeInForce::GoodTillCancel);
+        let json = serde_json::to_value(order).unwrap();
+        assert_eq!(json["owner"], order.owner.to_string());
+        assert_eq!(serde_json::from_value::<Order>(json).unwrap(), order);
+    }
+
+    fn market_with_lot_sizes(base_lot_size: i64, quote_lot_size: i64) -> crate::state::market::PerpMarket {
+        crate::state::market::PerpMarket {
+            admin: solana_program::pubkey::Pubkey::default(),
+            base_mint: solana_program::pubkey::Pubkey::default(),
+            quote_mint: solana_program::pubkey::Pubkey::default(),
+            bids: solana_program::pubkey::Pubkey::default(),
+            asks: solana_program::pubkey::Pubkey::default(),
+            event_queue: solana_program::pubkey::Pubkey::default(),
+            base_lot_size,
+            quote_lot_size,
+            taker_fee_bps: 0,
+            maker_fee_bps: 0,
+            max_expired_opposing_drops_per_place: 5,
+            max_expired_own_side_drops_per_place: 1,
+            mode: crate::state::market::MarketMode::Active,
+            matching_policy: crate::state::market::MatchingPolicy::PriceTime,
+            emit_maker_out_on_fill: false,
+            fee_penalty_bps: 0,
+            fee_penalty_fill_threshold_bps: 0,
+            total_orders_placed: 0,
+            total_base_lots_filled: 0,
+            fee_rounding: crate::state::market::FeeRounding::Truncate,
+            match_on_touch: true,
+            max_self_trade_cancels_per_place: 5,
+            force_self_trade_prevention: false,
+            open_interest_base_lots: 0,
+        }
+    }
+
+    #[test]
+    fn locked_amounts_for_a_bid_locks_quote_only() {
+        let mut order = order_with_tif(TimeInForce::GoodTillCancel);
+        order.side = Side::Bid;
+        order.price_lots = 100;
+        order.quantity_lots = 10;
+        let market = market_with_lot_sizes(1, 1);
+
+        assert_eq!(order.locked_amounts(&market), (0, 1_000));
+    }
+
+    #[test]
+    fn locked_amounts_for_an_ask_locks_base_only() {
+        let mut order = order_with_tif(TimeInForce::GoodTillCancel);
+        order.side = Side::Ask;
+        order.price_lots = 100;
+        order.quantity_lots = 10;
+        let market = market_with_lot_sizes(1, 1);
+
+        assert_eq!(order.locked_amounts(&market), (10, 0));
+    }
+
+    #[test]
+    fn split_at_fill_fully_taken_leaves_nothing_resting() {
+        let mut order = order_with_tif(TimeInForce::GoodTillCancel);
+        order.quantity_lots = 10;
+
+        let (taken, resting) = order.split_at_fill(10, 1_000);
+        assert_eq!(taken, TakenPart { base_lots: 10, quote_lots: 1_000 });
+        assert_eq!(resting, RestingPart { quantity_lots: 0 });
+    }
+
+    #[test]
+    fn split_at_fill_partially_taken_splits_the_remainder() {
+        let mut order = order_with_tif(TimeInForce::GoodTillCancel);
+        order.quantity_lots = 10;
+
+        let (taken, resting) = order.split_at_fill(4, 400);
+        assert_eq!(taken, TakenPart { base_lots: 4, quote_lots: 400 });
+        assert_eq!(resting, RestingPart { quantity_lots: 6 });
+    }
+
+    #[test]
+    fn split_at_fill_fully_resting_takes_nothing() {
+        let mut order = order_with_tif(TimeInForce::GoodTillCancel);
+        order.quantity_lots = 10;
+
+        let (taken, resting) = order.split_at_fill(0, 0);
+        assert_eq!(taken, TakenPart { base_lots: 0, quote_lots: 0 });
+        assert_eq!(resting, RestingPart { quantity_lots: 10 });
+    }
+
+    #[test]
+    fn locked_amounts_convert_lots_using_market_lot_sizes() {
+        let mut order = order_with_tif(TimeInForce::GoodTillCancel);
+        order.side = Side::Bid;
+        order.price_lots = 100;
+        order.quantity_lots = 10;
+        let market = market_with_lot_sizes(1, 5);
+
+        assert_eq!(order.locked_amounts(&market), (0, 5_000));
+    }
+
+    #[test]
+    fn estimate_fees_matches_taker_fee_bps_of_the_fill_notional() {
+        let order = order_with_tif(TimeInForce::GoodTillCancel);
+        let mut market = market_with_lot_sizes(1, 1);
+        market.taker_fee_bps = 100; // 1%
+
+        // 10 base lots at 100 quote lots each = 1_000 quote native; 1% is 10.
+        assert_eq!(order.estimate_fees(&market, 10, 100), 10);
+    }
+
+    #[test]
+    fn estimate_fees_matches_a_real_fill_produced_by_new_order() {
+        use crate::state::{event_queue::{EventQueue, FillEvent}, orderbook::Orderbook};
+
+        let mut market = market_with_lot_sizes(1, 1);
+        market.taker_fee_bps = 100;
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+
+        let maker = OrderParamsBuilder::new(Side::Ask, 100, 5).build();
+        book.new_order(&mut eq, &mut market, solana_program::pubkey::Pubkey::new_from_array([1; 32]), 1, maker, 0)
+            .unwrap();
+
+        let taker = OrderParamsBuilder::new(Side::Bid, 100, 5).build();
+        let taker_order = order_with_tif(TimeInForce::GoodTillCancel);
+        book.new_order(&mut eq, &mut market, solana_program::pubkey::Pubkey::new_from_array([2; 32]), 2, taker, 0)
+            .unwrap();
+
+        let fill: FillEvent = eq.pop_front().unwrap().decode::<FillEvent>().unwrap().to_owned();
+        let estimated = taker_order.estimate_fees(&market, fill.quantity_lots, fill.price_lots);
+        let actual_taker_fee = fill.settle(&market).taker_quote_delta.abs()
+            - fill.quantity_lots * fill.price_lots * market.quote_lot_size;
+
+        assert_eq!(estimated, actual_taker_fee);
+    }
+
+    #[test]
+    fn required_lock_for_a_limit_bid_uses_its_own_price() {
+        let params = OrderParamsBuilder::new(Side::Bid, 100, 10).build();
+        let market = market_with_lot_sizes(1, 1);
+
+        assert_eq!(params.required_lock(&market, 999), (0, 1_000));
+    }
+
+    #[test]
+    fn required_lock_for_a_limit_ask_locks_base_only() {
+        let params = OrderParamsBuilder::new(Side::Ask, 100, 10).build();
+        let market = market_with_lot_sizes(1, 1);
+
+        assert_eq!(params.required_lock(&market, 999), (10, 0));
+    }
+
+    #[test]
+    fn required_lock_for_a_market_bid_uses_the_oracle_price() {
+        let params = OrderParamsBuilder::new(Side::Bid, 0, 10)
+            .order_type(OrderType::Market)
+            .build();
+        let market = market_with_lot_sizes(1, 1);
+
+        assert_eq!(params.required_lock(&market, 150), (0, 1_500));
+    }
+
+    #[test]
+    fn required_lock_for_a_bid_is_capped_by_max_quote_lots() {
+        let params = OrderParamsBuilder::new(Side::Bid, 100, 10)
+            .max_quote_lots(400)
+            .build();
+        let market = market_with_lot_sizes(1, 1);
+
+        assert_eq!(params.required_lock(&market, 999), (0, 400));
+    }
+
+    #[test]
+    fn builder_matches_a_hand_constructed_limit_bid() {
+        let built = OrderParamsBuilder::new(Side::Bid, 100, 10).build();
+
+        let hand = OrderParams {
+            side: Side::Bid,
+            price_lots: 100,
+            max_base_lots: 10,
+            max_quote_lots: i64::MAX,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::GoodTillCancel,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            strategy_id: 0,
+            reduce_only: false,
+            reference_price_lots: None,
+            max_price_deviation_bps: 0,
+        };
+
+        assert_eq!(built, hand);
+    }
+
+    #[test]
+    fn builder_matches_a_hand_constructed_ioc() {
+        let built = OrderParamsBuilder::new(Side::Ask, 100, 10)
+            .order_type(OrderType::ImmediateOrCancel)
+            .max_quote_lots(5_000)
+            .client_order_id(42)
+            .self_trade_behavior(SelfTradeBehavior::CancelProvide)
+            .strategy_id(3)
+            .reduce_only(true)
+            .reference_price_guard(200, 50)
+            .build();
+
+        let hand = OrderParams {
+            side: Side::Ask,
+            price_lots: 100,
+            max_base_lots: 10,
+            max_quote_lots: 5_000,
+            order_type: OrderType::ImmediateOrCancel,
+            time_in_force: TimeInForce::GoodTillCancel,
+            client_order_id: 42,
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            strategy_id: 3,
+            reduce_only: true,
+            reference_price_lots: Some(200),
+            max_price_deviation_bps: 50,
+        };
+
+        assert_eq!(built, hand);
+    }
+}
@@ -0,0 +1,348 @@
+//! Price/time-ordered storage for the orders resting on one side of the
+//! book.
+//!
+//! Orders live in a fixed-size slab (`slots`) addressed by a stable
+//! [`Handle`]; a free list holds the unused slots and recycles vacated
+//! ones. A [`BTreeMap`] keyed on
+//! a packed `(price, sequence)` value gives O(log n) access to the best
+//! and worst orders without scanning the slab.
+
+use {
+    crate::{error::OrderbookError, state::order::{Order, Side}},
+    std::collections::BTreeMap,
+};
+
+/// Maximum number of resting orders on one side of the book.
+pub const MAX_ORDERS_PER_SIDE: usize = 512;
+
+/// A stable reference to a slot in an [`OrderTree`]'s slab. Remains valid
+/// until the order it points to is removed.
+pub type Handle = u32;
+
+#[derive(Clone, Copy, Debug)]
+struct Leaf {
+    key: u128,
+    order: Order,
+}
+
+/// Price/time-ordered collection of the [`Order`]s resting on one side of
+/// the book.
+pub struct OrderTree {
+    side: Side,
+    slots: Vec<Option<Leaf>>,
+    free_list: Vec<Handle>,
+    index: BTreeMap<u128, Handle>,
+    next_seq_num: u64,
+}
+
+impl OrderTree {
+    pub fn new(side: Side) -> Self {
+        Self {
+            side,
+            slots: vec![None; MAX_ORDERS_PER_SIDE],
+            free_list: (0..MAX_ORDERS_PER_SIDE as Handle).rev().collect(),
+            index: BTreeMap::new(),
+            next_seq_num: 0,
+        }
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// Packs a price and insertion sequence number into a single key such
+    /// that, for this tree's side, the best order is always at one
+    /// extreme of the index.
+    ///
+    /// The sequence number is inverted on the bid side: `BTreeMap` only
+    /// gives cheap access to its minimum and maximum key, so for bids
+    /// (best = highest price, and on a tie, earliest order) we need the
+    /// earliest order to produce the *largest* key at a given price.
+    fn node_key(&self, price_lots: i64, seq_num: u64) -> u128 {
+        debug_assert!(
+            price_lots >= crate::state::orderbook::MIN_PRICE_LOTS,
+            "node keys are only packed for prices at or above MIN_PRICE_LOTS"
+        );
+        let price_bits = (price_lots as u64) ^ (1u64 << 63);
+        let seq_component = match self.side {
+            Side::Bid => u64::MAX - seq_num,
+            Side::Ask => seq_num,
+        };
+        ((price_bits as u128) << 64) | seq_component as u128
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.next_seq_num;
+        self.next_seq_num += 1;
+        seq
+    }
+
+    /// Inserts `order` into the tree, returning the handle it can later
+    /// be looked up or removed by.
+    ///
+    /// `order.order_id` is never checked for uniqueness here: this
+    /// tree's index key is `(price, insertion sequence)`, and
+    /// `next_seq` hands out a fresh sequence number on every call, so
+    /// the key this produces can never already be present in `index` —
+    /// there's no ambiguous "replace or error" case to define at this
+    /// layer. A duplicate `order_id` is rejected one level up, in
+    /// [`Orderbook::new_order`](super::orderbook::Orderbook::new_order),
+    /// before it ever reaches `insert`.
+    pub fn insert(&mut self, order: Order) -> Result<Handle, OrderbookError> {
+        // The single choke point every resting order passes through, so
+        // this is the one place that can actually guarantee no
+        // non-positive price ever lands on the book — `node_key`'s
+        // `debug_assert` below catches the same thing, but only in debug
+        // builds. Every other reader that divides by a resting order's
+        // price (e.g. `BookSide::quantity_at_price_with_quote_cap_bounded`,
+        // the matching loop's `remaining_quote / maker.price_lots`) relies
+        // on this holding in every build profile.
+        if order.price_lots < crate::state::orderbook::MIN_PRICE_LOTS {
+            return Err(OrderbookError::InvalidPrice);
+        }
+        let handle = self.free_list.pop().ok_or(OrderbookError::SomeError)?;
+        let seq_num = self.next_seq();
+        let key = self.node_key(order.price_lots, seq_num);
+        debug_assert!(!self.index.contains_key(&key), "node_key collided despite a monotonic sequence number");
+        self.slots[handle as usize] = Some(Leaf { key, order });
+        self.index.insert(key, handle);
+        #[cfg(test)]
+        crate::state::compute_counter::record_tree_op();
+        Ok(handle)
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&Order> {
+        self.slots[handle as usize].as_ref().map(|leaf| &leaf.order)
+    }
+
+    /// Reduces the resting quantity of the order at `handle` in place.
+    /// The order's priority (its key) is unaffected.
+    pub fn decrement_quantity(&mut self, handle: Handle, quantity_lots: i64) {
+        if let Some(leaf) = self.slots[handle as usize].as_mut() {
+            leaf.order.quantity_lots -= quantity_lots;
+        }
+    }
+
+    pub fn remove_by_handle(&mut self, handle: Handle) -> Option<Order> {
+        let leaf = self.slots[handle as usize].take()?;
+        self.index.remove(&leaf.key);
+        self.free_list.push(handle);
+        #[cfg(test)]
+        crate::state::compute_counter::record_tree_op();
+        Some(leaf.order)
+    }
+
+    /// The handle and order with the best price (and, on ties, earliest
+    /// time) priority.
+    pub fn best(&self) -> Option<(Handle, &Order)> {
+        let (_, handle) = match self.side {
+            Side::Bid => self.index.iter().next_back(),
+            Side::Ask => self.index.iter().next(),
+        }?;
+        self.get(*handle).map(|order| (*handle, order))
+    }
+
+    /// The handle and order with the worst price priority.
+    pub fn worst(&self) -> Option<(Handle, &Order)> {
+        let (_, handle) = match self.side {
+            Side::Bid => self.index.iter().next(),
+            Side::Ask => self.index.iter().next_back(),
+        }?;
+        self.get(*handle).map(|order| (*handle, order))
+    }
+
+    /// Linear scan for the handle of the order with this id. The tree is
+    /// indexed by price/time key, not order id, so this is O(n); callers
+    /// that need this frequently should cache the handle instead.
+    pub fn find_by_order_id(&self, order_id: u128) -> Option<Handle> {
+        self.index
+            .values()
+            .copied()
+            .find(|&handle| self.get(handle).is_some_and(|order| order.order_id == order_id))
+    }
+
+    /// The handle of the leaf at exactly `(price_lots, seq_num)`, an O(log
+    /// n) lookup against `index` rather than the O(n) scan
+    /// [`find_by_order_id`](Self::find_by_order_id) needs. `seq_num` is
+    /// the value handed out by [`next_seq`](Self::next_seq) when the
+    /// order was inserted, not anything derivable from the order itself,
+    /// so this is only useful to a caller that already tracked it (e.g.
+    /// to re-locate a handle it deliberately let go stale).
+    pub(crate) fn find_handle(&self, price_lots: i64, seq_num: u64) -> Option<Handle> {
+        self.index.get(&self.node_key(price_lots, seq_num)).copied()
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        self.index.len() as u64
+    }
+
+    /// The maximum number of orders this tree can hold at once, fixed by
+    /// the size of its slab.
+    pub fn capacity(&self) -> usize {
+        MAX_ORDERS_PER_SIDE
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.leaf_count() as usize >= self.capacity()
+    }
+
+    /// Orders in priority order (best first).
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &Order)> {
+        let handles: Vec<Handle> = match self.side {
+            Side::Bid => self.index.values().rev().copied().collect(),
+            Side::Ask => self.index.values().copied().collect(),
+        };
+        handles
+            .into_iter()
+            .map(move |handle| (handle, self.get(handle).unwrap()))
+    }
+
+    /// The number of vacated slots below the highest occupied one: the
+    /// holes that inserts and removes have left in the slab since the tree
+    /// was last dense. Unused slots above the highest occupied one are not
+    /// counted.
+    pub fn free_node_count(&self) -> usize {
+        let high_water = self.index.values().max().map_or(0, |&handle| handle as usize + 1);
+        self.free_list.iter().filter(|&&handle| (handle as usize) < high_water).count()
+    }
+
+    /// Rebuilds the slab densely, packing every resting order into the
+    /// lowest-numbered slots and discarding the fragmented free list.
+    /// Useful before snapshotting a tree that has seen a lot of
+    /// insert/remove churn.
+    ///
+    /// Preserves every leaf's key and data, so [`iter`](Self::iter),
+    /// [`best`](Self::best) and [`worst`](Self::worst) are unaffected —
+    /// keys, not slot indices, determine priority order. Handles are
+    /// reassigned, though, so this invalidates any `Handle` obtained
+    /// before the call; only run it between operations, never with a
+    /// handle still in scope.
+    ///
+    /// Returns [`OrderbookError::CorruptNode`] instead of panicking if
+    /// `index` ever references a slab slot that isn't occupied, which
+    /// should never happen but would otherwise surface as an opaque
+    /// panic deep inside a maintenance operation.
+    pub fn try_compact(&mut self) -> Result<(), OrderbookError> {
+        let leaves: Vec<Leaf> = self
+            .index
+            .values()
+            .map(|&handle| self.slots[handle as usize].ok_or(OrderbookError::CorruptNode))
+            .collect::<Result<_, _>>()?;
+
+        self.slots = vec![None; MAX_ORDERS_PER_SIDE];
+        self.index.clear();
+        self.free_list.clear();
+
+        for (handle, leaf) in leaves.into_iter().enumerate() {
+            let handle = handle as Handle;
+            self.index.insert(leaf.key, handle);
+            self.slots[handle as usize] = Some(leaf);
+        }
+        let live = self.index.len() as Handle;
+        self.free_list = (live..MAX_ORDERS_PER_SIDE as Handle).rev().collect();
+        Ok(())
+    }
+}
+
+// `node_key`'s packing is the load-bearing invariant behind every other
+// `OrderTree`/`BookSide` method (`best`, `worst`, `iter`, ...), so unlike
+// the rest of this type's behavior — exercised indirectly through
+// `book_side.rs`'s test module — its ordering across the full `u64`
+// sequence-number range is worth pinning directly here, where `node_key`
+// is still private and its inputs can be chosen freely.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_key_sorts_bids_by_price_desc_then_time_asc_at_the_seq_num_extremes() {
+        let tree = OrderTree::new(Side::Bid);
+        let (p1, p2) = (100i64, 200i64);
+
+        // Same price: the earlier sequence number must win (bid best =
+        // highest price, earliest time on a tie), which for a bid's
+        // inverted sequence component means the *larger* packed key.
+        assert!(tree.node_key(p1, 0) > tree.node_key(p1, u64::MAX));
+        assert!(tree.node_key(p1, 0) > tree.node_key(p1, 1));
+        assert!(tree.node_key(p1, u64::MAX - 1) > tree.node_key(p1, u64::MAX));
+
+        // Price dominates sequence number regardless of where in the
+        // u64 range the sequence numbers fall.
+        assert!(tree.node_key(p2, u64::MAX) > tree.node_key(p1, 0));
+        assert!(tree.node_key(p2, 0) > tree.node_key(p1, u64::MAX));
+    }
+
+    #[test]
+    fn node_key_sorts_asks_by_price_asc_then_time_asc_at_the_seq_num_extremes() {
+        let tree = OrderTree::new(Side::Ask);
+        let (p1, p2) = (100i64, 200i64);
+
+        // Same price: the earlier sequence number must win (ask best =
+        // lowest price, earliest time on a tie), which for an ask's
+        // uninverted sequence component means the *smaller* packed key.
+        assert!(tree.node_key(p1, 0) < tree.node_key(p1, u64::MAX));
+        assert!(tree.node_key(p1, 0) < tree.node_key(p1, 1));
+        assert!(tree.node_key(p1, u64::MAX - 1) < tree.node_key(p1, u64::MAX));
+
+        // Price dominates sequence number regardless of where in the
+        // u64 range the sequence numbers fall.
+        assert!(tree.node_key(p1, u64::MAX) < tree.node_key(p2, 0));
+        assert!(tree.node_key(p1, 0) < tree.node_key(p2, u64::MAX));
+    }
+
+    #[test]
+    fn try_compact_reports_corrupt_node_instead_of_panicking() {
+        let mut tree = OrderTree::new(Side::Ask);
+        let handle = tree
+            .insert(Order {
+                order_id: 1,
+                owner: solana_program::pubkey::Pubkey::new_from_array([1; 32]),
+                side: Side::Ask,
+                price_lots: 100,
+                quantity_lots: 1,
+                order_type: crate::state::order::OrderType::Limit,
+                time_in_force: crate::state::order::TimeInForce::GoodTillCancel,
+                timestamp: 0,
+                client_order_id: 0,
+                strategy_id: 0,
+            })
+            .unwrap();
+
+        // `index` still points at `handle`, but the slab slot it names is
+        // vacated directly (only possible from within this module, since
+        // both fields are private) to simulate the corruption this method
+        // guards against.
+        tree.slots[handle as usize] = None;
+
+        assert_eq!(tree.try_compact(), Err(OrderbookError::CorruptNode));
+    }
+
+    #[test]
+    fn find_handle_locates_a_deep_key_and_misses_a_key_never_inserted() {
+        let mut tree = OrderTree::new(Side::Ask);
+        let mut handles = Vec::new();
+        for i in 0..20 {
+            let handle = tree
+                .insert(Order {
+                    order_id: i as u128,
+                    owner: solana_program::pubkey::Pubkey::new_from_array([1; 32]),
+                    side: Side::Ask,
+                    price_lots: 100 + i,
+                    quantity_lots: 1,
+                    order_type: crate::state::order::OrderType::Limit,
+                    time_in_force: crate::state::order::TimeInForce::GoodTillCancel,
+                    timestamp: 0,
+                    client_order_id: 0,
+                    strategy_id: 0,
+                })
+                .unwrap();
+            handles.push(handle);
+        }
+
+        // Order 15 was the 16th insert (seq_nums start at 0), so it holds
+        // seq_num 15 at price 115.
+        assert_eq!(tree.find_handle(115, 15), Some(handles[15]));
+        assert_eq!(tree.find_handle(115, 999), None);
+        assert_eq!(tree.find_handle(999, 15), None);
+    }
+}
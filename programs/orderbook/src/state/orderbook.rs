@@ -0,0 +1,3102 @@
+//! The matching engine: two [`BookSide`]s and the logic that matches new
+//! orders against them.
+
+use {
+    crate::{
+        error::OrderbookError,
+        state::{
+            book_side::BookSide,
+            event_queue::{EventQueue, FillEvent, OutEvent},
+            market::{MatchingPolicy, PerpMarket},
+            order::{Order, OrderParams, OrderParamsBuilder, OrderType, Side},
+        },
+    },
+    solana_program::pubkey::Pubkey,
+};
+
+/// The lowest price, in lots, an order may ever be posted at.
+/// [`OrderTree::insert`](super::order_tree::OrderTree::insert) rejects
+/// anything lower, and every reader that divides by a resting order's
+/// price relies on it being positive, so this is the floor
+/// [`OrderType::PostOnlySlide`] clamps against instead of sliding through.
+pub const MIN_PRICE_LOTS: i64 = 1;
+
+/// The outcome of [`Orderbook::new_order`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrderResult {
+    /// The taker fee, in basis points, to charge for this order.
+    pub taker_fee_bps: i64,
+    /// The handle of the order's resting remainder, so a caller can act
+    /// on it further in the same transaction (e.g. tag it in an index)
+    /// without a fresh lookup. `None` if the order left nothing resting:
+    /// it filled in full, or was `Market`/`ImmediateOrCancel`, which
+    /// never rest.
+    pub posted_handle: Option<crate::state::order_tree::Handle>,
+    /// Base lots requested but neither filled nor posted. Always `0` for
+    /// order types that rest (`Limit`/`PostOnly`/`PostOnlySlide`), since
+    /// any leftover quantity there posts instead of being dropped.
+    pub unfilled_base_lots: i64,
+    /// `true` when `unfilled_base_lots` is nonzero because this order's
+    /// type (`Market`/`ImmediateOrCancel`) never rests, rather than the
+    /// order having simply filled in full. Lets a caller distinguish "the
+    /// order was ioc/market-truncated, consider resubmitting the rest"
+    /// from "there's nothing left to resubmit."
+    pub ioc_truncated: bool,
+    /// The price of the last (i.e. worst-priced) fill this call caused,
+    /// or `None` if it matched nothing. The matching loop always walks
+    /// the opposing side best price first, so this is simply the price
+    /// of the final fill pushed. TWAP/iceberg executors track this
+    /// alongside the average fill price to decide whether the book has
+    /// gotten too thin to keep sweeping.
+    pub worst_fill_price_lots: Option<i64>,
+}
+
+/// A compact risk snapshot of the whole book: level counts, total
+/// resting quantity, and the best price on each side, plus how many
+/// resting orders across both sides have already expired as of the
+/// snapshot's `now_ts`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BookSummary {
+    pub bid_levels: u32,
+    pub ask_levels: u32,
+    pub bid_total: i64,
+    pub ask_total: i64,
+    pub best_bid: Option<i64>,
+    pub best_ask: Option<i64>,
+    pub num_expired: u32,
+}
+
+/// A compact ticker snapshot of the current top of book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ticker {
+    pub best_bid: Option<i64>,
+    pub best_ask: Option<i64>,
+    pub bid_qty: Option<i64>,
+    pub ask_qty: Option<i64>,
+    pub mid: Option<i64>,
+    pub spread: Option<i64>,
+}
+
+/// The live order book for one market: the resting bids and asks it
+/// takes incoming orders against.
+pub struct Orderbook {
+    pub bids: BookSide,
+    pub asks: BookSide,
+}
+
+impl Orderbook {
+    pub fn new() -> Self {
+        Self {
+            bids: BookSide::new(Side::Bid),
+            asks: BookSide::new(Side::Ask),
+        }
+    }
+
+    fn side(&self, side: Side) -> &BookSide {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BookSide {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    /// The mid price between the best bid and best ask, or `None` if
+    /// either side of the book is empty.
+    pub fn mid_price(&self) -> Option<i64> {
+        Some((self.bids.best_price()? + self.asks.best_price()?) / 2)
+    }
+
+    /// The gap between the best ask and the best bid, or `None` if
+    /// either side of the book is empty.
+    pub fn spread(&self) -> Option<i64> {
+        Some(self.asks.best_price()? - self.bids.best_price()?)
+    }
+
+    /// Whether the book is internally crossed: the best bid at or above
+    /// the best ask. A well-formed book should never end up crossed once
+    /// `new_order` finishes matching; this is meant for invariant checks
+    /// in tests and fuzzing rather than the hot path, so `now_ts` isn't
+    /// used yet — expired resting orders are cheap enough at the top of
+    /// book that filtering them hasn't been worth the extra traversal.
+    pub fn is_crossed(&self, _now_ts: i64) -> bool {
+        match (self.bids.best_price(), self.asks.best_price()) {
+            (Some(bid), Some(ask)) => bid >= ask,
+            _ => false,
+        }
+    }
+
+    /// A single-pass snapshot of the top of book, suitable for a
+    /// lightweight ticker.
+    pub fn ticker(&self, _now_ts: i64) -> Ticker {
+        let best_bid_order = self.bids.best_order();
+        let best_ask_order = self.asks.best_order();
+        let best_bid = best_bid_order.map(|o| o.price_lots);
+        let best_ask = best_ask_order.map(|o| o.price_lots);
+        Ticker {
+            best_bid,
+            best_ask,
+            bid_qty: best_bid_order.map(|o| o.quantity_lots),
+            ask_qty: best_ask_order.map(|o| o.quantity_lots),
+            mid: match (best_bid, best_ask) {
+                (Some(bid), Some(ask)) => Some((bid + ask) / 2),
+                _ => None,
+            },
+            spread: match (best_bid, best_ask) {
+                (Some(bid), Some(ask)) => Some(ask - bid),
+                _ => None,
+            },
+        }
+    }
+
+    /// Combined depth view merging both sides into a single sequence of
+    /// `(side, price_lots, quantity_lots)` levels, aggregated per price
+    /// via [`BookSide::to_levels`]. Bids come first, best (highest)
+    /// price first, followed by asks, best (lowest) price first — the
+    /// natural order for stacking into a two-column ladder display. An
+    /// empty side simply contributes no levels.
+    pub fn iter_levels(&self, now_ts: i64) -> impl Iterator<Item = (Side, i64, i64)> {
+        self.bids
+            .to_levels(now_ts)
+            .into_iter()
+            .map(|(price, qty)| (Side::Bid, price, qty))
+            .chain(
+                self.asks
+                    .to_levels(now_ts)
+                    .into_iter()
+                    .map(|(price, qty)| (Side::Ask, price, qty)),
+            )
+    }
+
+    /// The total number of orders currently resting on either side of the
+    /// book, including expired ones not yet swept out. A simple
+    /// aggregation over both sides' [`OrderTree::leaf_count`](super::order_tree::OrderTree::leaf_count)
+    /// for account/market dashboards that would otherwise have to reach
+    /// into `bids`/`asks` themselves.
+    pub fn total_orders(&self) -> u32 {
+        (self.bids.tree().leaf_count() + self.asks.tree().leaf_count()) as u32
+    }
+
+    /// The number of `owner`'s non-expired resting orders across both
+    /// sides as of `now_ts`. Built on [`BookSide::iter_owner`].
+    pub fn total_orders_by_owner(&self, owner: &Pubkey, now_ts: i64) -> u32 {
+        (self.bids.iter_owner(owner, now_ts).count() + self.asks.iter_owner(owner, now_ts).count()) as u32
+    }
+
+    /// A risk-engine-friendly snapshot of the whole book, computed in one
+    /// bounded pass per side rather than composing `price_levels`/
+    /// `to_levels`/`best_price`, each of which would walk the side again.
+    pub fn book_summary(&self, now_ts: i64) -> BookSummary {
+        let (bid_levels, bid_total, best_bid, bid_expired) = Self::side_summary(&self.bids, now_ts);
+        let (ask_levels, ask_total, best_ask, ask_expired) = Self::side_summary(&self.asks, now_ts);
+        BookSummary {
+            bid_levels,
+            ask_levels,
+            bid_total,
+            ask_total,
+            best_bid,
+            best_ask,
+            num_expired: bid_expired + ask_expired,
+        }
+    }
+
+    /// `(distinct occupied price levels, total resting quantity, best
+    /// price, expired order count)` for one side as of `now_ts`, in a
+    /// single pass. Building block for [`Orderbook::book_summary`].
+    fn side_summary(side: &BookSide, now_ts: i64) -> (u32, i64, Option<i64>, u32) {
+        let mut levels = 0u32;
+        let mut total = 0i64;
+        let mut best = None;
+        let mut expired = 0u32;
+        let mut last_price = None;
+        for order in side.iter_all_including_invalid() {
+            if order.is_expired(now_ts) {
+                expired += 1;
+                continue;
+            }
+            if best.is_none() {
+                best = Some(order.price_lots);
+            }
+            total += order.quantity_lots;
+            if last_price != Some(order.price_lots) {
+                levels += 1;
+                last_price = Some(order.price_lots);
+            }
+        }
+        (levels, total, best, expired)
+    }
+
+    /// The resting order a taker on `taker_side` at `limit_price_lots`
+    /// would match first, or `None` if nothing on the opposing side
+    /// crosses that price. Skips expired orders. Useful for debugging
+    /// and routing decisions that want to know the exact fill candidate
+    /// without going through [`new_order`](Self::new_order).
+    pub fn next_match(&self, taker_side: Side, limit_price_lots: i64, now_ts: i64) -> Option<&Order> {
+        self.side(taker_side.invert_side())
+            .iter_all_including_invalid()
+            .find(|order| !order.is_expired(now_ts) && Side::would_cross(taker_side, limit_price_lots, order.price_lots))
+    }
+
+    /// Whether an incoming order on `side` with `limit_price` (`None` for
+    /// a `Market` order, which crosses at any price) would match a
+    /// resting order at `maker_price`. Centralizes the cross check used
+    /// by both the match loop and the `PostOnly` rejection path.
+    ///
+    /// `match_on_touch` governs the boundary where the two prices are
+    /// exactly equal: `true` treats it as a match, like `Side::would_cross`
+    /// does; `false` treats it as merely touching the spread, so the
+    /// order posts instead. Some venues want the latter to avoid a
+    /// resting order being taken out at its own limit price.
+    fn crosses(side: Side, limit_price: Option<i64>, maker_price: i64, match_on_touch: bool) -> bool {
+        match limit_price {
+            None => true,
+            Some(limit) if limit == maker_price => match_on_touch,
+            Some(limit) => Side::would_cross(side, limit, maker_price),
+        }
+    }
+
+    /// The quote-lot notional of a fill for `match_quantity` base lots at
+    /// `price_lots`, checked so a pathologically large price/quantity
+    /// pair can't silently wrap into a bogus fill.
+    fn checked_match_quote_lots(match_quantity: i64, price_lots: i64) -> Result<i64, OrderbookError> {
+        match_quantity
+            .checked_mul(price_lots)
+            .ok_or(OrderbookError::MathError)
+    }
+
+    /// Subtracts `amount` from `remaining`, the way the match loop debits
+    /// a taker's remaining base lots as it fills against makers.
+    /// `amount` should never exceed `remaining` by construction, so a
+    /// debug build asserts the invariant to catch a matching bug at the
+    /// call site; a release build instead returns
+    /// [`OrderbookError::MathError`] so an arithmetic bug fails the
+    /// instruction instead of panicking and aborting the transaction.
+    fn checked_debit(remaining: i64, amount: i64) -> Result<i64, OrderbookError> {
+        debug_assert!(amount <= remaining, "debiting more than what remains");
+        remaining
+            .checked_sub(amount)
+            .filter(|&r| r >= 0)
+            .ok_or(OrderbookError::MathError)
+    }
+
+    /// Matches up to `desired` base lots against every non-expired
+    /// resting order at `price_lots` on `maker_side`, allocating each
+    /// order a share proportional to its quantity within the level
+    /// rather than filling strictly by time priority. Returns the total
+    /// base lots and quote lots matched.
+    ///
+    /// Self-trade prevention is not applied within a pro-rata level: the
+    /// taker's own resting orders at this price participate in the
+    /// proration like any other maker, regardless of
+    /// `self_trade_behavior`. Markets that enable
+    /// [`MatchingPolicy::ProRata`] should not rely on self-trade
+    /// prevention until that's implemented.
+    #[allow(clippy::too_many_arguments)]
+    fn match_pro_rata_level(
+        &mut self,
+        event_queue: &mut EventQueue,
+        maker_side: Side,
+        price_lots: i64,
+        desired: i64,
+        taker: Pubkey,
+        taker_order_id: u128,
+        taker_strategy_id: u8,
+        taker_side: Side,
+        now_ts: i64,
+        emit_maker_out_on_fill: bool,
+    ) -> Result<(i64, i64), OrderbookError> {
+        let level: Vec<(crate::state::order_tree::Handle, Pubkey, u128, i64, i64, u8)> = self
+            .side(maker_side)
+            .tree()
+            .iter()
+            .filter(|(_, o)| o.price_lots == price_lots && !o.is_expired(now_ts))
+            .map(|(handle, o)| (handle, o.owner, o.order_id, o.quantity_lots, o.timestamp, o.strategy_id))
+            .collect();
+        let level_total: i64 = level.iter().map(|(_, _, _, quantity_lots, _, _)| quantity_lots).sum();
+        if level_total <= 0 || desired <= 0 {
+            return Ok((0, 0));
+        }
+
+        let mut matched_base = 0i64;
+        let mut matched_quote = 0i64;
+        for (handle, maker_owner, maker_order_id, maker_quantity_lots, maker_timestamp, maker_strategy_id) in level {
+            if matched_base >= desired {
+                break;
+            }
+            let share = (maker_quantity_lots as i128 * desired as i128) / level_total as i128;
+            let match_quantity = (share as i64).clamp(1, maker_quantity_lots).min(desired - matched_base);
+
+            event_queue.push_back(
+                FillEvent {
+                    event_type: crate::state::event_queue::EventType::Fill,
+                    taker_side,
+                    maker: maker_owner,
+                    taker,
+                    maker_order_id,
+                    taker_order_id,
+                    price_lots,
+                    quantity_lots: match_quantity,
+                    maker_timestamp,
+                    timestamp: now_ts,
+                    maker_strategy_id,
+                    taker_strategy_id,
+                }
+                .into(),
+            )?;
+            #[cfg(test)]
+            crate::state::compute_counter::record_event_pushed();
+
+            if maker_quantity_lots - match_quantity == 0 {
+                self.side_mut(maker_side).remove_by_handle(handle);
+                if emit_maker_out_on_fill {
+                    event_queue.push_back(
+                        OutEvent {
+                            event_type: crate::state::event_queue::EventType::Out,
+                            side: maker_side,
+                            owner: maker_owner,
+                            order_id: maker_order_id,
+                            quantity_lots: match_quantity,
+                            out_reason: crate::state::event_queue::OutReason::Filled,
+                            timestamp: now_ts,
+                            strategy_id: maker_strategy_id,
+                        }
+                        .into(),
+                    )?;
+                }
+            } else {
+                self.side_mut(maker_side)
+                    .tree_mut()
+                    .decrement_quantity(handle, match_quantity);
+            }
+
+            matched_base += match_quantity;
+            matched_quote = matched_quote
+                .checked_add(Self::checked_match_quote_lots(match_quantity, price_lots)?)
+                .ok_or(OrderbookError::MathError)?;
+        }
+
+        Ok((matched_base, matched_quote))
+    }
+
+    /// Matches an incoming order against the opposite side of the book,
+    /// emitting a [`FillEvent`] for every maker it trades against, then
+    /// (for order types that rest) posts any remaining quantity to this
+    /// order's own side.
+    ///
+    /// Returns an [`OrderResult`] with the taker fee, in basis points,
+    /// that should be charged for this order: `market.taker_fee_bps` for
+    /// every order type except `ImmediateOrCancel`, which instead goes
+    /// through [`PerpMarket::ioc_taker_fee_bps`] so a barely-filled ioc
+    /// can incur `fee_penalty_bps`. Also carries the handle of the
+    /// order's resting remainder, if any.
+    pub fn new_order(
+        &mut self,
+        event_queue: &mut EventQueue,
+        market: &mut PerpMarket,
+        owner: Pubkey,
+        order_id: u128,
+        params: OrderParams,
+        now_ts: i64,
+    ) -> Result<OrderResult, OrderbookError> {
+        // `bids`/`asks` are public fields, so nothing at the type level
+        // stops a caller from assigning a `BookSide` built for the wrong
+        // side into one of them (e.g. `book.bids = BookSide::new(Side::Ask)`),
+        // which would silently invert its price ordering. Catch that
+        // here rather than let matching quietly misbehave.
+        debug_assert_eq!(self.bids.side(), Side::Bid, "Orderbook.bids holds a BookSide built for the wrong side");
+        debug_assert_eq!(self.asks.side(), Side::Ask, "Orderbook.asks holds a BookSide built for the wrong side");
+
+        if params.max_base_lots <= 0 || params.max_quote_lots <= 0 {
+            return Err(OrderbookError::InvalidQuantity);
+        }
+        // `Market` orders never use `price_lots` as a real limit (matching
+        // passes `None` for it regardless of what's supplied) or rest, so
+        // it's exempt. Every other order type either rests at this price
+        // (`Limit`/`PostOnly`/`PostOnlySlide`) or uses it as a taker limit
+        // (`ImmediateOrCancel`), and a resting price is also the divisor
+        // in the matching loop's `remaining_quote / maker.price_lots` —
+        // a non-positive price there would panic, and below
+        // `MIN_PRICE_LOTS` it would corrupt this side's node-key ordering.
+        if params.order_type != OrderType::Market && params.price_lots < MIN_PRICE_LOTS {
+            return Err(OrderbookError::InvalidPrice);
+        }
+        if market.is_reduce_only() || market.is_force_close() {
+            return Err(OrderbookError::MarketNotAcceptingOrders);
+        }
+        if self.bids.tree().find_by_order_id(order_id).is_some()
+            || self.asks.tree().find_by_order_id(order_id).is_some()
+        {
+            // `order_id` is caller-supplied (deterministic replay,
+            // migration, ...) rather than always derived fresh, so it
+            // isn't guaranteed unique the way an incrementing sequence
+            // number would be.
+            return Err(OrderbookError::DuplicateKey);
+        }
+
+        let taker_side = params.side;
+        let maker_side = taker_side.invert_side();
+        let mut order_price_lots = params.price_lots;
+
+        if matches!(params.order_type, OrderType::PostOnly | OrderType::PostOnlySlide) {
+            if let Some(best) = self.side(maker_side).best_price() {
+                if Self::crosses(taker_side, Some(order_price_lots), best, market.match_on_touch) {
+                    if params.order_type == OrderType::PostOnlySlide {
+                        // Slide to the best price adjacent to the
+                        // opposing top that doesn't cross it, rather
+                        // than rejecting the order outright. This is
+                        // unaffected by `reduce_only`: sliding never
+                        // causes a match, so a reduce-only slide always
+                        // posts just like a non-reduce-only one.
+                        let slid_price = match taker_side {
+                            Side::Bid => best - 1,
+                            Side::Ask => best + 1,
+                        };
+                        // A bid sliding down through `MIN_PRICE_LOTS`
+                        // has nowhere valid left to land: there's no
+                        // slide that both avoids the cross and stays
+                        // postable, so this is refused like a plain
+                        // `PostOnly` cross rather than silently clamped
+                        // to a price that would still cross.
+                        if taker_side == Side::Bid && slid_price < MIN_PRICE_LOTS {
+                            return Err(OrderbookError::PostOnlyWouldCross);
+                        }
+                        order_price_lots = slid_price;
+                    } else {
+                        return Err(OrderbookError::PostOnlyWouldCross);
+                    }
+                }
+            }
+        }
+
+        let limit_price = match params.order_type {
+            OrderType::Market => None,
+            _ => Some(order_price_lots),
+        };
+
+        // For `Market`/`ImmediateOrCancel` orders, an absolute band around
+        // an external reference price (e.g. an oracle) guards against
+        // sweeping deep into a stale book. This is separate from the
+        // order's own limit price: it doesn't apply to resting order
+        // types, which never fill against a stale book at placement time.
+        let price_guard_limit = if matches!(params.order_type, OrderType::Market | OrderType::ImmediateOrCancel) {
+            params.reference_price_lots.map(|reference_price_lots| {
+                let deviation = reference_price_lots.saturating_mul(params.max_price_deviation_bps) / 10_000;
+                match taker_side {
+                    Side::Bid => reference_price_lots.saturating_add(deviation),
+                    Side::Ask => reference_price_lots.saturating_sub(deviation),
+                }
+            })
+        } else {
+            None
+        };
+
+        // `force_self_trade_prevention` overrides whatever the order
+        // itself asked for: an integrator running a market-wide
+        // wash-trading policy shouldn't have to trust every client to set
+        // `self_trade_behavior` correctly.
+        let effective_self_trade_behavior = if market.force_self_trade_prevention {
+            crate::state::order::SelfTradeBehavior::AbortTransaction
+        } else {
+            params.self_trade_behavior
+        };
+
+        let mut remaining_base = params.max_base_lots;
+        let mut remaining_quote = params.max_quote_lots;
+        let mut opposing_expired_drops: u8 = 0;
+        let mut self_trade_cancels: u8 = 0;
+        let mut posted_handle = None;
+        let mut worst_fill_price_lots: Option<i64> = None;
+        loop {
+            if remaining_base <= 0 || remaining_quote <= 0 {
+                break;
+            }
+            let Some((handle, maker)) = self.side(maker_side).tree().best() else {
+                break;
+            };
+            #[cfg(test)]
+            crate::state::compute_counter::record_node_visit();
+            let maker_owner = maker.owner;
+            let maker_order_id = maker.order_id;
+            let maker_quantity_lots = maker.quantity_lots;
+            let maker_strategy_id = maker.strategy_id;
+
+            if maker.is_expired(now_ts) {
+                if opposing_expired_drops >= market.max_expired_opposing_drops_per_place
+                    || event_queue.is_full()
+                {
+                    // Hit the per-place cap, or the event queue has no
+                    // room left to record the drop: leave the remaining
+                    // expired makers for a later place or a crank instead
+                    // of burning unbounded compute on this one, or
+                    // failing the whole transaction just to clean up.
+                    break;
+                }
+                self.side_mut(maker_side).remove_by_handle(handle);
+                event_queue.push_back(
+                    OutEvent {
+                        event_type: crate::state::event_queue::EventType::Out,
+                        side: maker_side,
+                        owner: maker_owner,
+                        order_id: maker_order_id,
+                        quantity_lots: maker_quantity_lots,
+                        out_reason: crate::state::event_queue::OutReason::Expired,
+                        timestamp: now_ts,
+                        strategy_id: maker_strategy_id,
+                    }
+                    .into(),
+                )?;
+                opposing_expired_drops += 1;
+                continue;
+            }
+
+            if !Self::crosses(taker_side, limit_price, maker.price_lots, market.match_on_touch) {
+                break;
+            }
+
+            if maker_owner == owner {
+                match effective_self_trade_behavior {
+                    crate::state::order::SelfTradeBehavior::AbortTransaction => {
+                        return Err(OrderbookError::SelfTrade);
+                    }
+                    crate::state::order::SelfTradeBehavior::CancelProvide => {
+                        if self_trade_cancels >= market.max_self_trade_cancels_per_place {
+                            // Hit the per-place cap: stop cancelling. An
+                            // order that never rests leaves the remaining
+                            // self-crossing makers resting; one that rests
+                            // meets them again in the safety net below.
+                            break;
+                        }
+                        self.side_mut(maker_side).remove_by_handle(handle);
+                        event_queue.push_back(
+                            OutEvent {
+                                event_type: crate::state::event_queue::EventType::Out,
+                                side: maker_side,
+                                owner: maker_owner,
+                                order_id: maker_order_id,
+                                quantity_lots: maker_quantity_lots,
+                                out_reason: crate::state::event_queue::OutReason::SelfTradeCancel,
+                                timestamp: now_ts,
+                                strategy_id: maker_strategy_id,
+                            }
+                            .into(),
+                        )?;
+                        self_trade_cancels += 1;
+                        continue;
+                    }
+                    crate::state::order::SelfTradeBehavior::DecrementTake => {
+                        let self_match = remaining_base.min(maker_quantity_lots);
+                        if maker_quantity_lots - self_match == 0 {
+                            self.side_mut(maker_side).remove_by_handle(handle);
+                        } else {
+                            self.side_mut(maker_side)
+                                .tree_mut()
+                                .decrement_quantity(handle, self_match);
+                        }
+                        remaining_base = Self::checked_debit(remaining_base, self_match)?;
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(limit) = price_guard_limit {
+                let breaches_guard = match taker_side {
+                    Side::Bid => maker.price_lots > limit,
+                    Side::Ask => maker.price_lots < limit,
+                };
+                if breaches_guard {
+                    // The book is sorted best-to-worst on this side, so
+                    // once one maker breaches the band every maker after
+                    // it does too; stop and drop the remainder instead of
+                    // continuing to scan.
+                    break;
+                }
+            }
+
+            // `max_quote_lots` is a hard cap on notional spent, not just
+            // on the number of base lots: even a `Market` order with a
+            // huge `max_base_lots` must stop once its quote budget runs
+            // out.
+            //
+            // Breaking (rather than `continue`-ing to the next maker) the
+            // moment the current best maker is unaffordable is
+            // deliberate, not a missed "keep walking to a cheaper level"
+            // case: `self.side(maker_side).tree().best()` is already the
+            // single most favorable remaining price for this taker (the
+            // lowest ask or highest bid), and every later maker this loop
+            // would reach is strictly worse. If the budget can't afford
+            // even one lot here, it can't afford one anywhere else on the
+            // book either, so there is no cheaper level being skipped.
+            let affordable_base = remaining_quote / maker.price_lots;
+            if affordable_base <= 0 {
+                break;
+            }
+
+            let desired = remaining_base.min(affordable_base);
+            let price_lots = maker.price_lots;
+
+            if market.matching_policy == MatchingPolicy::ProRata {
+                let (matched_base, matched_quote) = self.match_pro_rata_level(
+                    event_queue,
+                    maker_side,
+                    price_lots,
+                    desired,
+                    owner,
+                    order_id,
+                    params.strategy_id,
+                    taker_side,
+                    now_ts,
+                    market.emit_maker_out_on_fill,
+                )?;
+                remaining_base = Self::checked_debit(remaining_base, matched_base)?;
+                remaining_quote = remaining_quote
+                    .checked_sub(matched_quote)
+                    .ok_or(OrderbookError::MathError)?;
+                market.total_base_lots_filled = market.total_base_lots_filled.saturating_add(matched_base as u64);
+                if matched_base > 0 {
+                    worst_fill_price_lots = Some(price_lots);
+                }
+                continue;
+            }
+
+            let match_quantity = desired.min(maker.quantity_lots);
+            let maker_order_id = maker.order_id;
+            let maker_owner = maker.owner;
+            let maker_timestamp = maker.timestamp;
+            let maker_remaining = maker.quantity_lots - match_quantity;
+
+            event_queue.push_back(
+                FillEvent {
+                    event_type: crate::state::event_queue::EventType::Fill,
+                    taker_side,
+                    maker: maker_owner,
+                    taker: owner,
+                    maker_order_id,
+                    taker_order_id: order_id,
+                    price_lots,
+                    quantity_lots: match_quantity,
+                    maker_timestamp,
+                    timestamp: now_ts,
+                    maker_strategy_id,
+                    taker_strategy_id: params.strategy_id,
+                }
+                .into(),
+            )?;
+            #[cfg(test)]
+            crate::state::compute_counter::record_event_pushed();
+            worst_fill_price_lots = Some(price_lots);
+
+            if maker_remaining == 0 {
+                self.side_mut(maker_side).remove_by_handle(handle);
+                if market.emit_maker_out_on_fill {
+                    event_queue.push_back(
+                        OutEvent {
+                            event_type: crate::state::event_queue::EventType::Out,
+                            side: maker_side,
+                            owner: maker_owner,
+                            order_id: maker_order_id,
+                            quantity_lots: match_quantity,
+                            out_reason: crate::state::event_queue::OutReason::Filled,
+                            timestamp: now_ts,
+                            strategy_id: maker_strategy_id,
+                        }
+                        .into(),
+                    )?;
+                }
+            } else {
+                self.side_mut(maker_side)
+                    .tree_mut()
+                    .decrement_quantity(handle, match_quantity);
+            }
+
+            let match_quote_lots = Self::checked_match_quote_lots(match_quantity, price_lots)?;
+            remaining_base = Self::checked_debit(remaining_base, match_quantity)?;
+            remaining_quote = remaining_quote
+                .checked_sub(match_quote_lots)
+                .ok_or(OrderbookError::MathError)?;
+            market.total_base_lots_filled = market.total_base_lots_filled.saturating_add(match_quantity as u64);
+        }
+
+        let rests = matches!(params.order_type, OrderType::Limit | OrderType::PostOnly | OrderType::PostOnlySlide);
+        if rests && remaining_base > 0 {
+            self.remove_expired(
+                event_queue,
+                taker_side,
+                now_ts,
+                market.max_expired_own_side_drops_per_place,
+            )?;
+
+            // The matching loop above can stop before reaching an
+            // owner-owned maker that this order would still cross — the
+            // event queue filling up, the expired-drop cap, or the
+            // reference-price guard can all end the loop early. Without
+            // this, the order could post and leave the book crossed
+            // against the owner's own resting order. Resolve any such
+            // maker per `self_trade_behavior` before posting, exactly as
+            // the matching loop would have.
+            while remaining_base > 0 {
+                let Some((handle, best_maker)) = self.side(maker_side).tree().best() else {
+                    break;
+                };
+                if best_maker.owner != owner
+                    || !Self::crosses(taker_side, Some(order_price_lots), best_maker.price_lots, market.match_on_touch)
+                {
+                    break;
+                }
+                let maker_owner = best_maker.owner;
+                let maker_order_id = best_maker.order_id;
+                let maker_quantity_lots = best_maker.quantity_lots;
+                let maker_strategy_id = best_maker.strategy_id;
+
+                match effective_self_trade_behavior {
+                    crate::state::order::SelfTradeBehavior::AbortTransaction => {
+                        return Err(OrderbookError::SelfTrade);
+                    }
+                    crate::state::order::SelfTradeBehavior::CancelProvide => {
+                        if self_trade_cancels >= market.max_self_trade_cancels_per_place {
+                            // Unlike the main loop's cap break (which can
+                            // defer to this safety net), this is the last
+                            // chance to resolve the self-cross: a
+                            // same-owner maker still crosses `order_price_lots`
+                            // and there's no cancellation budget left to
+                            // remove it. Posting anyway would leave the
+                            // book crossed against the owner's own resting
+                            // order, so reject the whole order instead.
+                            return Err(OrderbookError::SelfTradeCancelCapExceeded);
+                        }
+                        self.side_mut(maker_side).remove_by_handle(handle);
+                        event_queue.push_back(
+                            OutEvent {
+                                event_type: crate::state::event_queue::EventType::Out,
+                                side: maker_side,
+                                owner: maker_owner,
+                                order_id: maker_order_id,
+                                quantity_lots: maker_quantity_lots,
+                                out_reason: crate::state::event_queue::OutReason::SelfTradeCancel,
+                                timestamp: now_ts,
+                                strategy_id: maker_strategy_id,
+                            }
+                            .into(),
+                        )?;
+                        self_trade_cancels += 1;
+                    }
+                    crate::state::order::SelfTradeBehavior::DecrementTake => {
+                        let self_match = remaining_base.min(maker_quantity_lots);
+                        if maker_quantity_lots - self_match == 0 {
+                            self.side_mut(maker_side).remove_by_handle(handle);
+                        } else {
+                            self.side_mut(maker_side)
+                                .tree_mut()
+                                .decrement_quantity(handle, self_match);
+                        }
+                        remaining_base = Self::checked_debit(remaining_base, self_match)?;
+                    }
+                }
+            }
+
+            // The self-cross resolution above may have fully consumed
+            // the order via `DecrementTake`, leaving nothing left to
+            // post.
+            if remaining_base > 0 {
+                if self.side(taker_side).tree().leaf_count()
+                    >= crate::state::order_tree::MAX_ORDERS_PER_SIDE as u64
+                {
+                    let (worst_handle, worst_order) = self.side(taker_side).tree().worst().unwrap();
+                    let is_better_than_worst = match taker_side {
+                        Side::Bid => order_price_lots > worst_order.price_lots,
+                        Side::Ask => order_price_lots < worst_order.price_lots,
+                    };
+                    if !is_better_than_worst {
+                        return Err(OrderbookError::OutOfSpace);
+                    }
+
+                    // Make room by evicting the worst-priced resting order on
+                    // this side rather than rejecting the new one.
+                    let evicted = self
+                        .side_mut(taker_side)
+                        .remove_by_handle(worst_handle)
+                        .unwrap();
+                    event_queue.push_back(
+                        OutEvent {
+                            event_type: crate::state::event_queue::EventType::Out,
+                            side: taker_side,
+                            owner: evicted.owner,
+                            order_id: evicted.order_id,
+                            quantity_lots: evicted.quantity_lots,
+                            out_reason: crate::state::event_queue::OutReason::Evicted,
+                            timestamp: now_ts,
+                            strategy_id: evicted.strategy_id,
+                        }
+                        .into(),
+                    )?;
+                }
+
+                // `Market`/`ImmediateOrCancel` never rest (see `rests`
+                // above), so `order_price_lots` here is always a real
+                // limit price the client supplied or `PostOnlySlide`
+                // computed. (Matching gives a Market order no limit at all,
+                // `limit_price` is `None`, rather than a stand-in price.)
+                debug_assert!(
+                    !matches!(params.order_type, OrderType::Market | OrderType::ImmediateOrCancel),
+                    "order types that never rest must not reach the posting path"
+                );
+                debug_assert_ne!(order_price_lots, i64::MAX, "refusing to post an order at i64::MAX");
+                let order = Order {
+                    order_id,
+                    owner,
+                    side: taker_side,
+                    price_lots: order_price_lots,
+                    quantity_lots: remaining_base,
+                    order_type: params.order_type,
+                    time_in_force: params.time_in_force,
+                    timestamp: now_ts,
+                    client_order_id: params.client_order_id,
+                    strategy_id: params.strategy_id,
+                };
+                posted_handle = Some(self.side_mut(taker_side).tree_mut().insert(order)?);
+            }
+        }
+
+        let taker_fee_bps = if matches!(params.order_type, OrderType::ImmediateOrCancel) {
+            let filled_base_lots = params.max_base_lots - remaining_base;
+            market.ioc_taker_fee_bps(params.max_base_lots, filled_base_lots)
+        } else {
+            market.taker_fee_bps
+        };
+        market.total_orders_placed = market.total_orders_placed.saturating_add(1);
+        Ok(OrderResult {
+            taker_fee_bps,
+            posted_handle,
+            unfilled_base_lots: if rests { 0 } else { remaining_base },
+            ioc_truncated: !rests && remaining_base > 0,
+            worst_fill_price_lots,
+        })
+    }
+
+    /// Drops up to `max_removals` expired orders from `side`, emitting an
+    /// [`OutEvent`] for each. Used to reclaim slots on the book's own side
+    /// before posting a new order, bounded so a single place can't be
+    /// made to scan the whole side.
+    ///
+    /// If the event queue is already full, cleanup stops early rather
+    /// than failing the whole placement just to make room for orders
+    /// that will still be there for a later place or a crank.
+    fn remove_expired(
+        &mut self,
+        event_queue: &mut EventQueue,
+        side: Side,
+        now_ts: i64,
+        max_removals: u8,
+    ) -> Result<u8, OrderbookError> {
+        let mut removed = 0;
+        while removed < max_removals && !event_queue.is_full() {
+            let Some((handle, order)) = self
+                .side(side)
+                .tree()
+                .iter()
+                .find(|(_, order)| order.is_expired(now_ts))
+            else {
+                break;
+            };
+            let (owner, order_id, quantity_lots, strategy_id) =
+                (order.owner, order.order_id, order.quantity_lots, order.strategy_id);
+            self.side_mut(side).remove_by_handle(handle);
+            event_queue.push_back(
+                OutEvent {
+                    event_type: crate::state::event_queue::EventType::Out,
+                    side,
+                    owner,
+                    order_id,
+                    quantity_lots,
+                    out_reason: crate::state::event_queue::OutReason::Expired,
+                    timestamp: now_ts,
+                    strategy_id,
+                }
+                .into(),
+            )?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Cancels expired orders from both sides of the book, up to `limit`
+    /// combined, emitting an [`OutEvent`] with an `Expired` reason for
+    /// each. Intended for a periodic maintenance crank, unlike
+    /// [`remove_expired`](Self::remove_expired), which `new_order` calls
+    /// on just one side, bounded much more tightly, as part of its own
+    /// placement work.
+    pub fn cancel_expired_orders(
+        &mut self,
+        event_queue: &mut EventQueue,
+        now_ts: i64,
+        limit: u32,
+    ) -> Result<u32, OrderbookError> {
+        let mut removed = 0u32;
+        for side in [Side::Bid, Side::Ask] {
+            while removed < limit && !event_queue.is_full() {
+                let Some((handle, order)) = self
+                    .side(side)
+                    .tree()
+                    .iter()
+                    .find(|(_, order)| order.is_expired(now_ts))
+                else {
+                    break;
+                };
+                let (owner, order_id, quantity_lots, strategy_id) =
+                    (order.owner, order.order_id, order.quantity_lots, order.strategy_id);
+                self.side_mut(side).remove_by_handle(handle);
+                event_queue.push_back(
+                    OutEvent {
+                        event_type: crate::state::event_queue::EventType::Out,
+                        side,
+                        owner,
+                        order_id,
+                        quantity_lots,
+                        out_reason: crate::state::event_queue::OutReason::Expired,
+                        timestamp: now_ts,
+                        strategy_id,
+                    }
+                    .into(),
+                )?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Cancels a single resting order, emitting an [`OutEvent`] for it.
+    pub fn cancel_order(
+        &mut self,
+        event_queue: &mut EventQueue,
+        side: Side,
+        handle: crate::state::order_tree::Handle,
+        now_ts: i64,
+    ) -> Result<Order, OrderbookError> {
+        let order = self
+            .side_mut(side)
+            .remove_by_handle(handle)
+            .ok_or(OrderbookError::OrderNotFound)?;
+        event_queue.push_back(
+            OutEvent {
+                event_type: crate::state::event_queue::EventType::Out,
+                side,
+                owner: order.owner,
+                order_id: order.order_id,
+                quantity_lots: order.quantity_lots,
+                out_reason: crate::state::event_queue::OutReason::Cancelled,
+                timestamp: now_ts,
+                strategy_id: order.strategy_id,
+            }
+            .into(),
+        )?;
+        Ok(order)
+    }
+
+    /// Cancels a single resting order looked up by its order id,
+    /// verifying `owner` matches.
+    pub fn cancel_order_by_id(
+        &mut self,
+        event_queue: &mut EventQueue,
+        owner: Pubkey,
+        side: Side,
+        order_id: u128,
+        now_ts: i64,
+    ) -> Result<Order, OrderbookError> {
+        let handle = self
+            .side(side)
+            .tree()
+            .find_by_order_id(order_id)
+            .ok_or(OrderbookError::OrderIdNotFound)?;
+        if self.side(side).tree().get(handle).unwrap().owner != owner {
+            return Err(OrderbookError::NotOrderOwner);
+        }
+        self.cancel_order(event_queue, side, handle, now_ts)
+    }
+
+    /// Cancels a single resting order looked up by its order id, like
+    /// [`cancel_order_by_id`](Self::cancel_order_by_id), additionally
+    /// returning the native-unit amounts freed back to `owner` (via
+    /// [`Order::locked_amounts`]) so a caller doesn't have to re-derive
+    /// them from the cancelled order and the market's lot sizes.
+    /// Returns `(order, quote_freed, base_freed)` — quote before base,
+    /// the reverse of `locked_amounts`'s own `(base, quote)` order.
+    pub fn cancel_order_by_id_refund(
+        &mut self,
+        event_queue: &mut EventQueue,
+        market: &PerpMarket,
+        owner: Pubkey,
+        side: Side,
+        order_id: u128,
+        now_ts: i64,
+    ) -> Result<(Order, i64, i64), OrderbookError> {
+        let order = self.cancel_order_by_id(event_queue, owner, side, order_id, now_ts)?;
+        let (base_freed, quote_freed) = order.locked_amounts(market);
+        Ok((order, quote_freed, base_freed))
+    }
+
+    /// Finds and cancels `owner`'s worst-priced resting order on `side`,
+    /// emitting an [`OutEvent`] for it. Intended for margin/liquidation
+    /// flows that need to free up an owner's furthest-from-market
+    /// exposure first. Returns `None` if the owner has no resting orders
+    /// on that side.
+    pub fn cancel_owner_worst(
+        &mut self,
+        event_queue: &mut EventQueue,
+        owner: Pubkey,
+        side: Side,
+        now_ts: i64,
+    ) -> Result<Option<Order>, OrderbookError> {
+        // `iter()` yields best-first for either side, so the owner's
+        // worst order is simply the last matching entry.
+        let worst_owned = self
+            .side(side)
+            .tree()
+            .iter()
+            .filter(|(_, order)| order.owner == owner)
+            .last();
+        let Some((handle, _)) = worst_owned else {
+            return Ok(None);
+        };
+        self.cancel_order(event_queue, side, handle, now_ts).map(Some)
+    }
+
+    /// `owner`'s resting orders on the side opposite `new_side` that a
+    /// new order at `new_price_lots` would cross, as of `now_ts`. Lets a
+    /// client check for a self-cross before placing, so it can pick an
+    /// appropriate `self_trade_behavior` up front instead of discovering
+    /// the clash only from the resulting events.
+    pub fn self_crossing_orders(
+        &self,
+        owner: Pubkey,
+        new_side: Side,
+        new_price_lots: i64,
+        now_ts: i64,
+    ) -> Vec<(crate::state::order_tree::Handle, &Order)> {
+        let maker_side = new_side.invert_side();
+        self.side(maker_side)
+            .tree()
+            .iter()
+            .filter(|(_, order)| {
+                order.owner == owner
+                    && !order.is_expired(now_ts)
+                    && Side::would_cross(new_side, new_price_lots, order.price_lots)
+            })
+            .collect()
+    }
+
+    /// Cancels up to `limit` of `owner`'s resting orders on `side` whose
+    /// `timestamp` predates `before_ts`, emitting an [`OutEvent`] for
+    /// each. Lets a strategy expire its own stale quotes in bulk without
+    /// tracking individual order ids. Returns the number cancelled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cancel_orders_placed_before(
+        &mut self,
+        event_queue: &mut EventQueue,
+        owner: Pubkey,
+        side: Side,
+        before_ts: i64,
+        limit: u32,
+        now_ts: i64,
+    ) -> Result<u32, OrderbookError> {
+        let mut cancelled = 0;
+        while cancelled < limit {
+            let Some((handle, _)) = self
+                .side(side)
+                .tree()
+                .iter()
+                .find(|(_, order)| order.owner == owner && order.timestamp < before_ts)
+            else {
+                break;
+            };
+            self.cancel_order(event_queue, side, handle, now_ts)?;
+            cancelled += 1;
+        }
+        Ok(cancelled)
+    }
+
+    /// Cancels `old_order_id` and places `new_params` as `new_order_id`
+    /// in a single call. If the cancel fails (order not found, or not
+    /// owned by `owner`), the new order is never placed. Returns the new
+    /// order's [`OrderResult`]; see [`Orderbook::new_order`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn replace_order(
+        &mut self,
+        event_queue: &mut EventQueue,
+        market: &mut PerpMarket,
+        owner: Pubkey,
+        old_order_id: u128,
+        old_side: Side,
+        new_order_id: u128,
+        new_params: OrderParams,
+        now_ts: i64,
+    ) -> Result<OrderResult, OrderbookError> {
+        self.cancel_order_by_id(event_queue, owner, old_side, old_order_id, now_ts)?;
+        self.new_order(event_queue, market, owner, new_order_id, new_params, now_ts)
+    }
+
+    /// Decrements a resting order's quantity by `remove_lots`, removing
+    /// it entirely if that brings it to zero. Returns the order's
+    /// remaining quantity (`0` if it was removed). Emits an [`OutEvent`]
+    /// for the removed amount either way.
+    pub fn reduce_order(
+        &mut self,
+        event_queue: &mut EventQueue,
+        owner: Pubkey,
+        order_id: u128,
+        side: Side,
+        remove_lots: i64,
+        now_ts: i64,
+    ) -> Result<i64, OrderbookError> {
+        let handle = self
+            .side(side)
+            .tree()
+            .find_by_order_id(order_id)
+            .ok_or(OrderbookError::OrderIdNotFound)?;
+        let order = *self.side(side).tree().get(handle).unwrap();
+        if order.owner != owner {
+            return Err(OrderbookError::NotOrderOwner);
+        }
+
+        let removed = remove_lots.min(order.quantity_lots);
+        let remaining = order.quantity_lots - removed;
+
+        if remaining <= 0 {
+            self.side_mut(side).remove_by_handle(handle);
+        } else {
+            self.side_mut(side).tree_mut().decrement_quantity(handle, removed);
+        }
+
+        event_queue.push_back(
+            OutEvent {
+                event_type: crate::state::event_queue::EventType::Out,
+                side,
+                owner,
+                order_id,
+                quantity_lots: removed,
+                out_reason: crate::state::event_queue::OutReason::Cancelled,
+                timestamp: now_ts,
+                strategy_id: order.strategy_id,
+            }
+            .into(),
+        )?;
+
+        Ok(remaining.max(0))
+    }
+}
+
+impl Default for Orderbook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owner(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    fn market() -> PerpMarket {
+        PerpMarket {
+            admin: Pubkey::default(),
+            base_mint: Pubkey::default(),
+            quote_mint: Pubkey::default(),
+            bids: Pubkey::default(),
+            asks: Pubkey::default(),
+            event_queue: Pubkey::default(),
+            base_lot_size: 1,
+            quote_lot_size: 1,
+            taker_fee_bps: 0,
+            maker_fee_bps: 0,
+            max_expired_opposing_drops_per_place: 5,
+            max_expired_own_side_drops_per_place: 1,
+            mode: crate::state::market::MarketMode::Active,
+            matching_policy: MatchingPolicy::PriceTime,
+            emit_maker_out_on_fill: false,
+            fee_penalty_bps: 0,
+            fee_penalty_fill_threshold_bps: 0,
+            total_orders_placed: 0,
+            total_base_lots_filled: 0,
+            fee_rounding: crate::state::market::FeeRounding::Truncate,
+            match_on_touch: true,
+            max_self_trade_cancels_per_place: 5,
+            force_self_trade_prevention: false,
+            open_interest_base_lots: 0,
+        }
+    }
+
+    fn limit(side: Side, price: i64, qty: i64) -> OrderParams {
+        OrderParams {
+            side,
+            price_lots: price,
+            max_base_lots: qty,
+            max_quote_lots: i64::MAX,
+            order_type: OrderType::Limit,
+            time_in_force: crate::state::order::TimeInForce::GoodTillCancel,
+            client_order_id: 0,
+            self_trade_behavior: crate::state::order::SelfTradeBehavior::DecrementTake,
+            strategy_id: 0,
+            reduce_only: false,
+            reference_price_lots: None,
+            max_price_deviation_bps: 0,
+        }
+    }
+
+    #[test]
+    fn new_order_returns_a_handle_that_resolves_to_the_posted_order() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let result = book
+            .new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+
+        let handle = result.posted_handle.unwrap();
+        let posted = book.bids.tree().get(handle).unwrap();
+        assert_eq!(posted.order_id, 1);
+        assert_eq!(posted.owner, owner(1));
+        assert_eq!(posted.quantity_lots, 10);
+    }
+
+    #[test]
+    fn market_order_leaves_no_leaf_even_when_given_the_sentinel_max_price() {
+        // A Market order's `price_lots` is never a real limit (matching
+        // uses `None` for it regardless of what's passed), so even a
+        // client mistakenly supplying `i64::MAX` here must never result
+        // in a posted leaf at that price: `rests` is `false` for
+        // `Market`, so any unfilled remainder is discarded, not posted.
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 3), 0)
+            .unwrap();
+
+        let market_buy = OrderParamsBuilder::new(Side::Bid, i64::MAX, 1_000)
+            .order_type(OrderType::Market)
+            .build();
+        let result = book.new_order(&mut eq, &mut market(), owner(2), 2, market_buy, 0).unwrap();
+
+        assert!(result.posted_handle.is_none());
+        assert!(result.ioc_truncated);
+        assert!(book.bids.is_empty(), "a Market order must never leave a resting leaf");
+        assert!(
+            book.asks.iter_all_including_invalid().all(|o| o.price_lots != i64::MAX),
+            "no leaf should ever be posted at the Market sentinel price"
+        );
+    }
+
+    #[test]
+    fn new_order_posted_handle_is_none_when_nothing_rests() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut ioc = limit(Side::Bid, 100, 5);
+        ioc.order_type = OrderType::ImmediateOrCancel;
+        let result = book.new_order(&mut eq, &mut market(), owner(2), 2, ioc, 0).unwrap();
+
+        assert_eq!(result.posted_handle, None);
+        // Filled in full, so there's nothing left to call ioc-truncated.
+        assert_eq!(result.unfilled_base_lots, 0);
+        assert!(!result.ioc_truncated);
+    }
+
+    #[test]
+    fn new_order_reports_ioc_truncation_on_a_partial_fill() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut ioc = limit(Side::Bid, 100, 12);
+        ioc.order_type = OrderType::ImmediateOrCancel;
+        let result = book.new_order(&mut eq, &mut market(), owner(2), 2, ioc, 0).unwrap();
+
+        assert_eq!(result.posted_handle, None);
+        assert_eq!(result.unfilled_base_lots, 7);
+        assert!(result.ioc_truncated);
+    }
+
+    #[test]
+    fn ticker_two_sided_book() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 101, 5), 0)
+            .unwrap();
+
+        let ticker = book.ticker(0);
+        assert_eq!(ticker.best_bid, Some(99));
+        assert_eq!(ticker.best_ask, Some(101));
+        assert_eq!(ticker.bid_qty, Some(10));
+        assert_eq!(ticker.ask_qty, Some(5));
+        assert_eq!(ticker.mid, Some(100));
+        assert_eq!(ticker.spread, Some(2));
+    }
+
+    #[test]
+    fn book_summary_matches_a_hand_built_book_with_expired_orders() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        // Two bid levels (99 and 98), one already-expired order sitting
+        // ahead of both.
+        let mut expired_bid = limit(Side::Bid, 100, 3);
+        expired_bid.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expired_bid, 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(3), 3, limit(Side::Bid, 99, 4), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(4), 4, limit(Side::Bid, 98, 2), 0)
+            .unwrap();
+        // One ask level (101), one already-expired order behind it.
+        book.new_order(&mut eq, &mut market(), owner(5), 5, limit(Side::Ask, 101, 6), 0)
+            .unwrap();
+        let mut expired_ask = limit(Side::Ask, 102, 7);
+        expired_ask.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(6), 6, expired_ask, 0)
+            .unwrap();
+
+        let summary = book.book_summary(20);
+        assert_eq!(summary.bid_levels, 2);
+        assert_eq!(summary.ask_levels, 1);
+        assert_eq!(summary.bid_total, 16);
+        assert_eq!(summary.ask_total, 6);
+        assert_eq!(summary.best_bid, Some(99));
+        assert_eq!(summary.best_ask, Some(101));
+        assert_eq!(summary.num_expired, 2);
+    }
+
+    #[test]
+    fn iter_levels_merges_both_sides_bids_then_asks_best_first() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Bid, 98, 3), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(3), 3, limit(Side::Ask, 101, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(4), 4, limit(Side::Ask, 102, 7), 0)
+            .unwrap();
+
+        let levels: Vec<(Side, i64, i64)> = book.iter_levels(0).collect();
+        assert_eq!(
+            levels,
+            vec![
+                (Side::Bid, 99, 10),
+                (Side::Bid, 98, 3),
+                (Side::Ask, 101, 5),
+                (Side::Ask, 102, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn iter_levels_skips_an_empty_side() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+
+        let levels: Vec<(Side, i64, i64)> = book.iter_levels(0).collect();
+        assert_eq!(levels, vec![(Side::Bid, 99, 10)]);
+    }
+
+    #[test]
+    fn total_orders_sums_both_sides() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Bid, 98, 3), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(3), 3, limit(Side::Ask, 101, 5), 0)
+            .unwrap();
+
+        assert_eq!(book.total_orders(), 3);
+    }
+
+    #[test]
+    fn total_orders_by_owner_counts_across_both_sides() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(1), 2, limit(Side::Ask, 101, 3), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 3, limit(Side::Bid, 98, 5), 0)
+            .unwrap();
+
+        assert_eq!(book.total_orders_by_owner(&owner(1), 0), 2);
+        assert_eq!(book.total_orders_by_owner(&owner(2), 0), 1);
+        assert_eq!(book.total_orders_by_owner(&owner(3), 0), 0);
+    }
+
+    #[test]
+    fn next_match_returns_the_best_crossing_opposing_order() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 101, 5), 0)
+            .unwrap();
+
+        let matched = book.next_match(Side::Bid, 101, 0).unwrap();
+        assert_eq!(matched.order_id, 1);
+        assert_eq!(matched.price_lots, 100);
+    }
+
+    #[test]
+    fn next_match_is_none_when_the_price_does_not_cross() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        assert!(book.next_match(Side::Bid, 99, 0).is_none());
+    }
+
+    #[test]
+    fn matching_three_levels_visits_bounded_nodes() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 1), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 101, 1), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(3), 3, limit(Side::Ask, 102, 1), 0)
+            .unwrap();
+
+        crate::state::compute_counter::reset();
+        book.new_order(&mut eq, &mut market(), owner(4), 4, limit(Side::Bid, 102, 3), 0)
+            .unwrap();
+
+        // Exactly one visit per matched level; a regression that rescans
+        // from the top on every iteration would blow this bound.
+        assert_eq!(crate::state::compute_counter::node_visits(), 3);
+        assert_eq!(crate::state::compute_counter::events_pushed(), 3);
+    }
+
+    #[test]
+    fn reduce_order_partial_keeps_order_resting() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+
+        let remaining = book
+            .reduce_order(&mut eq, owner(1), 1, Side::Bid, 4, 0)
+            .unwrap();
+
+        assert_eq!(remaining, 6);
+        assert_eq!(book.bids.best_order().unwrap().quantity_lots, 6);
+    }
+
+    #[test]
+    fn quote_budget_that_cant_afford_the_best_ask_aborts_without_walking_to_cheaper_asks() {
+        // A descending-from-the-taker's-view ask book: 100 is the best
+        // (cheapest) ask, 200 is strictly worse. `tree().best()` always
+        // hands the matching loop 100 first, so a quote budget too small
+        // to afford even one lot there can never "skip ahead" to 200 —
+        // there is nothing cheaper left once 100 is unaffordable.
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 200, 5), 0)
+            .unwrap();
+
+        let mut taker = limit(Side::Bid, 200, 10);
+        taker.max_quote_lots = 50;
+        let result = book.new_order(&mut eq, &mut market(), owner(3), 3, taker, 0).unwrap();
+
+        // Nothing fills, and the whole order rests instead of matching
+        // the 200 ask that's still affordable in isolation.
+        assert!(eq.is_empty());
+        assert_eq!(book.asks.tree().leaf_count(), 2);
+        let handle = result.posted_handle.unwrap();
+        assert_eq!(book.bids.tree().get(handle).unwrap().quantity_lots, 10);
+    }
+
+    #[test]
+    fn reduce_order_to_zero_removes_order() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+
+        let remaining = book
+            .reduce_order(&mut eq, owner(1), 1, Side::Bid, 10, 0)
+            .unwrap();
+
+        assert_eq!(remaining, 0);
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn market_order_respects_quote_budget_over_base_limit() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 10, 1_000), 0)
+            .unwrap();
+
+        let market_buy = OrderParams {
+            side: Side::Bid,
+            price_lots: 0,
+            max_base_lots: 1_000_000,
+            max_quote_lots: 55,
+            order_type: OrderType::Market,
+            time_in_force: crate::state::order::TimeInForce::GoodTillCancel,
+            client_order_id: 0,
+            self_trade_behavior: crate::state::order::SelfTradeBehavior::DecrementTake,
+            strategy_id: 0,
+            reduce_only: false,
+            reference_price_lots: None,
+            max_price_deviation_bps: 0,
+        };
+        book.new_order(&mut eq, &mut market(), owner(2), 2, market_buy, 0).unwrap();
+
+        // 55 quote lots / 10 price lots = 5 base lots, even though
+        // max_base_lots was effectively unbounded.
+        assert_eq!(book.asks.best_order().unwrap().quantity_lots, 995);
+    }
+
+    #[test]
+    fn price_time_fills_resting_orders_in_full_by_time_priority() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 100, 20), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(3), 3, limit(Side::Ask, 100, 30), 0)
+            .unwrap();
+
+        book.new_order(&mut eq, &mut market(), owner(4), 4, limit(Side::Bid, 100, 30), 0)
+            .unwrap();
+
+        // Earliest two makers filled in full before the latest is
+        // touched at all.
+        assert_eq!(book.asks.tree().find_by_order_id(1), None);
+        assert_eq!(book.asks.tree().find_by_order_id(2), None);
+        assert_eq!(book.asks.best_order().unwrap().order_id, 3);
+        assert_eq!(book.asks.best_order().unwrap().quantity_lots, 30);
+    }
+
+    #[test]
+    fn worst_fill_price_lots_is_the_last_level_a_sweep_touches() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 101, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(3), 3, limit(Side::Ask, 102, 10), 0)
+            .unwrap();
+
+        let result = book
+            .new_order(&mut eq, &mut market(), owner(4), 4, limit(Side::Bid, 102, 30), 0)
+            .unwrap();
+
+        assert_eq!(result.worst_fill_price_lots, Some(102));
+    }
+
+    #[test]
+    fn worst_fill_price_lots_is_none_when_nothing_matches() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+
+        let result = book
+            .new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Bid, 90, 10), 0)
+            .unwrap();
+
+        assert_eq!(result.worst_fill_price_lots, None);
+    }
+
+    #[test]
+    fn pro_rata_allocates_across_same_price_makers_proportionally() {
+        let mut pro_rata_market = market();
+        pro_rata_market.matching_policy = MatchingPolicy::ProRata;
+
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut pro_rata_market, owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut pro_rata_market, owner(2), 2, limit(Side::Ask, 100, 20), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut pro_rata_market, owner(3), 3, limit(Side::Ask, 100, 30), 0)
+            .unwrap();
+
+        book.new_order(&mut eq, &mut pro_rata_market, owner(4), 4, limit(Side::Bid, 100, 30), 0)
+            .unwrap();
+
+        // Every maker at the level is filled proportionally to its share
+        // of the 60 resting lots, rather than the earliest two absorbing
+        // the whole taker quantity.
+        let mut fills = Vec::new();
+        while let Ok(event) = eq.pop_front() {
+            fills.push(TryInto::<FillEvent>::try_into(event).unwrap());
+        }
+        assert_eq!(fills.len(), 3);
+        assert_eq!(fills.iter().map(|f| f.quantity_lots).sum::<i64>(), 30);
+        assert_eq!(fills[0].maker, owner(1));
+        assert_eq!(fills[0].quantity_lots, 5); // 10 / 60 * 30
+        assert_eq!(fills[1].maker, owner(2));
+        assert_eq!(fills[1].quantity_lots, 10); // 20 / 60 * 30
+        assert_eq!(fills[2].maker, owner(3));
+        assert_eq!(fills[2].quantity_lots, 15); // 30 / 60 * 30
+    }
+
+    #[test]
+    fn fully_filled_maker_gets_no_out_event_by_default() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Bid, 100, 10), 0)
+            .unwrap();
+
+        assert_eq!(eq.len(), 1);
+        assert_eq!(
+            TryInto::<FillEvent>::try_into(eq.pop_front().unwrap())
+                .unwrap()
+                .quantity_lots,
+            10
+        );
+    }
+
+    #[test]
+    fn fully_filled_maker_emits_out_event_when_flag_set() {
+        let mut flagged_market = market();
+        flagged_market.emit_maker_out_on_fill = true;
+
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut flagged_market, owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut flagged_market, owner(2), 2, limit(Side::Bid, 100, 10), 0)
+            .unwrap();
+
+        assert_eq!(eq.len(), 2);
+        let fill: FillEvent = eq.pop_front().unwrap().decode::<FillEvent>().unwrap().to_owned();
+        assert_eq!(fill.quantity_lots, 10);
+        let out: OutEvent = eq.pop_front().unwrap().decode::<OutEvent>().unwrap().to_owned();
+        assert_eq!(out.owner, owner(1));
+        assert_eq!(out.quantity_lots, 10);
+        assert_eq!(out.out_reason, crate::state::event_queue::OutReason::Filled);
+    }
+
+    #[test]
+    fn partially_filled_maker_never_emits_out_event() {
+        let mut flagged_market = market();
+        flagged_market.emit_maker_out_on_fill = true;
+
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut flagged_market, owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut flagged_market, owner(2), 2, limit(Side::Bid, 100, 4), 0)
+            .unwrap();
+
+        assert_eq!(eq.len(), 1);
+        assert_eq!(
+            TryInto::<FillEvent>::try_into(eq.pop_front().unwrap())
+                .unwrap()
+                .quantity_lots,
+            4
+        );
+    }
+
+    fn out_reason_of(event: crate::state::event_queue::AnyEvent) -> crate::state::event_queue::OutReason {
+        TryInto::<OutEvent>::try_into(event).unwrap().out_reason
+    }
+
+    #[test]
+    fn expired_maker_is_dropped_with_expired_reason() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut expiring = limit(Side::Ask, 100, 5);
+        expiring.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expiring, 0).unwrap();
+
+        // Taker arrives after expiry and should see an empty book.
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Bid, 100, 5), 20)
+            .unwrap();
+
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Expired);
+        assert!(book.asks.is_empty());
+    }
+
+    #[test]
+    fn self_trade_cancel_provide_removes_resting_order() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut taker = limit(Side::Bid, 100, 5);
+        taker.self_trade_behavior = crate::state::order::SelfTradeBehavior::CancelProvide;
+        book.new_order(&mut eq, &mut market(), owner(1), 2, taker, 0).unwrap();
+
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::SelfTradeCancel);
+        assert!(book.asks.is_empty());
+        // The taker's own order rests, since nothing matched it.
+        assert_eq!(book.bids.best_order().unwrap().quantity_lots, 5);
+    }
+
+    #[test]
+    fn self_trade_cancel_provide_bounded_by_the_cap_rejects_rather_than_posts_crossed() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut test_market = market();
+        test_market.max_self_trade_cancels_per_place = 5;
+
+        // Ten of the taker's own resting asks, all at a price its bid
+        // will touch, so an unbounded `CancelProvide` would cancel every
+        // one of them in a single place.
+        for i in 1..=10u128 {
+            book.new_order(&mut eq, &mut test_market, owner(1), i, limit(Side::Ask, 100, 1), 0)
+                .unwrap();
+        }
+
+        let mut taker = limit(Side::Bid, 100, 1_000);
+        taker.self_trade_behavior = crate::state::order::SelfTradeBehavior::CancelProvide;
+        // Five asks still cross at price 100 once the cap is hit, and
+        // there's no budget left to cancel them: posting the bid would
+        // leave the book crossed against the owner's own resting orders,
+        // so the whole order is rejected instead.
+        assert_eq!(
+            book.new_order(&mut eq, &mut test_market, owner(1), 11, taker, 0)
+                .unwrap_err(),
+            OrderbookError::SelfTradeCancelCapExceeded
+        );
+
+        let mut cancellations = 0;
+        while let Ok(event) = eq.pop_front() {
+            assert_eq!(out_reason_of(event), crate::state::event_queue::OutReason::SelfTradeCancel);
+            cancellations += 1;
+        }
+        assert_eq!(cancellations, 5);
+        assert_eq!(book.asks.tree().leaf_count(), 5);
+        // The bid was never posted, so the book isn't left crossed. (On
+        // chain, the whole instruction failing would also discard the
+        // five cancellations above along with it; this in-process
+        // `Orderbook` has no such rollback of its own, so this test only
+        // checks the property this function is actually responsible for.)
+        assert!(book.bids.best_order().is_none());
+        assert!(!book.is_crossed(0));
+    }
+
+    #[test]
+    fn self_trade_safety_net_cancels_own_resting_order_left_uncrossed_by_the_loop() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        // Two asks at the same price: the first belongs to someone else,
+        // the second to the taker itself. A quote budget that covers
+        // only the first leaves the second still crossing the taker's
+        // bid once the matching loop stops, so the safety net has to
+        // clean it up before the remainder posts.
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut taker = limit(Side::Bid, 100, 1_000);
+        taker.max_quote_lots = 500;
+        taker.self_trade_behavior = crate::state::order::SelfTradeBehavior::CancelProvide;
+        book.new_order(&mut eq, &mut market(), owner(2), 3, taker, 0).unwrap();
+
+        // The matching loop fills the other owner's ask, then the safety
+        // net cancels the taker's own leftover ask before posting.
+        let fill: FillEvent = eq.pop_front().unwrap().decode::<FillEvent>().unwrap().to_owned();
+        assert_eq!(fill.maker, owner(1));
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::SelfTradeCancel);
+        assert!(eq.is_empty());
+        assert!(book.asks.is_empty());
+        assert_eq!(book.bids.best_order().unwrap().quantity_lots, 995);
+    }
+
+    #[test]
+    fn self_trade_safety_net_aborts_when_configured_to() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut taker = limit(Side::Bid, 100, 1_000);
+        taker.max_quote_lots = 500;
+        taker.self_trade_behavior = crate::state::order::SelfTradeBehavior::AbortTransaction;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(2), 3, taker, 0)
+                .unwrap_err(),
+            OrderbookError::SelfTrade
+        );
+    }
+
+    #[test]
+    fn force_self_trade_prevention_overrides_decrement_take_with_an_abort() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut market = market();
+        market.force_self_trade_prevention = true;
+        let mut taker = limit(Side::Bid, 100, 5);
+        taker.self_trade_behavior = crate::state::order::SelfTradeBehavior::DecrementTake;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market, owner(1), 2, taker, 0).unwrap_err(),
+            OrderbookError::SelfTrade
+        );
+        // Nothing was cancelled or matched: the order was rejected before
+        // touching the book.
+        assert_eq!(book.asks.best_order().unwrap().quantity_lots, 5);
+    }
+
+    #[test]
+    fn self_trade_safety_net_decrements_without_an_event() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 100, 3), 0)
+            .unwrap();
+
+        let mut taker = limit(Side::Bid, 100, 1_000);
+        taker.max_quote_lots = 500;
+        taker.self_trade_behavior = crate::state::order::SelfTradeBehavior::DecrementTake;
+        book.new_order(&mut eq, &mut market(), owner(2), 3, taker, 0).unwrap();
+
+        // `DecrementTake` never emits an event, unlike `CancelProvide`.
+        let fill: FillEvent = eq.pop_front().unwrap().decode::<FillEvent>().unwrap().to_owned();
+        assert_eq!(fill.maker, owner(1));
+        assert!(eq.is_empty());
+        assert!(book.asks.is_empty());
+        assert_eq!(book.bids.best_order().unwrap().quantity_lots, 992);
+    }
+
+    #[test]
+    fn full_book_evicts_worst_order() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        for i in 0..crate::state::order_tree::MAX_ORDERS_PER_SIDE {
+            book.new_order(&mut eq, &mut market(), owner(1), i as u128, limit(Side::Bid, 100 + i as i64, 1), 0)
+                .unwrap();
+        }
+        // The worst bid is the lowest price, placed first.
+        assert_eq!(book.bids.worst_price(0, true), Some(100));
+
+        book.new_order(&mut eq, &mut market(), owner(2), 9_999, limit(Side::Bid, 200, 1), 0)
+            .unwrap();
+
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Evicted);
+        assert_eq!(book.bids.worst_price(0, true), Some(101));
+    }
+
+    #[test]
+    fn full_book_eviction_costs_a_bounded_number_of_slab_operations() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        for i in 0..crate::state::order_tree::MAX_ORDERS_PER_SIDE {
+            book.new_order(&mut eq, &mut market(), owner(1), i as u128, limit(Side::Bid, 100 + i as i64, 1), 0)
+                .unwrap();
+        }
+
+        crate::state::compute_counter::reset();
+        book.new_order(&mut eq, &mut market(), owner(2), 9_999, limit(Side::Bid, 200, 1), 0)
+            .unwrap();
+
+        // Evicting the worst resting order and posting the new one should
+        // touch the slab exactly twice (one remove, one insert), never
+        // scanning or rewriting the rest of the side. A regression that
+        // makes eviction proportional to book size would blow this bound.
+        assert_eq!(crate::state::compute_counter::tree_ops(), 2);
+    }
+
+    #[test]
+    fn full_book_rejects_order_not_better_than_worst() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        for i in 0..crate::state::order_tree::MAX_ORDERS_PER_SIDE {
+            book.new_order(&mut eq, &mut market(), owner(1), i as u128, limit(Side::Bid, 100 + i as i64, 1), 0)
+                .unwrap();
+        }
+        // The worst bid is 100; a new bid at 100 is no better and should
+        // be rejected rather than evicting anything.
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(2), 9_999, limit(Side::Bid, 100, 1), 0)
+                .unwrap_err(),
+            OrderbookError::OutOfSpace
+        );
+        assert_eq!(book.bids.tree().leaf_count(), crate::state::order_tree::MAX_ORDERS_PER_SIDE as u64);
+    }
+
+    #[test]
+    fn strategy_id_survives_placement_and_appears_on_events() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut maker = limit(Side::Ask, 100, 5);
+        maker.strategy_id = 7;
+        book.new_order(&mut eq, &mut market(), owner(1), 1, maker, 0).unwrap();
+
+        let mut taker = limit(Side::Bid, 100, 10);
+        taker.strategy_id = 9;
+        book.new_order(&mut eq, &mut market(), owner(2), 2, taker, 0).unwrap();
+
+        let fill: FillEvent = eq.pop_front().unwrap().decode::<FillEvent>().unwrap().to_owned();
+        assert_eq!(fill.maker_strategy_id, 7);
+        assert_eq!(fill.taker_strategy_id, 9);
+
+        // The unmatched remainder of the taker's order rests with its
+        // own strategy tag.
+        assert_eq!(book.bids.best_order().unwrap().strategy_id, 9);
+
+        let canceled = book
+            .cancel_order_by_id(&mut eq, owner(2), Side::Bid, 2, 0)
+            .unwrap();
+        assert_eq!(canceled.strategy_id, 9);
+        let out: OutEvent = eq.pop_front().unwrap().decode::<OutEvent>().unwrap().to_owned();
+        assert_eq!(out.strategy_id, 9);
+    }
+
+    #[test]
+    fn opposing_expired_drops_cap_stops_matching_early() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut expired = limit(Side::Ask, 100, 1);
+        expired.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expired, 0).unwrap();
+        expired.price_lots = 101;
+        book.new_order(&mut eq, &mut market(), owner(1), 2, expired, 0).unwrap();
+        expired.price_lots = 102;
+        book.new_order(&mut eq, &mut market(), owner(1), 3, expired, 0).unwrap();
+
+        let mut capped_market = market();
+        capped_market.max_expired_opposing_drops_per_place = 2;
+        book.new_order(&mut eq, &mut capped_market, owner(2), 4, limit(Side::Bid, 102, 1), 20)
+            .unwrap();
+
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Expired);
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Expired);
+        assert!(eq.is_empty());
+        // The cap stopped matching after two drops, leaving the third
+        // expired ask resting and the taker unmatched (it posts instead).
+        assert_eq!(book.asks.tree().leaf_count(), 1);
+        assert_eq!(book.bids.best_order().unwrap().quantity_lots, 1);
+    }
+
+    #[test]
+    fn own_side_expired_drop_cap_limits_pre_post_cleanup() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut expired = limit(Side::Bid, 90, 1);
+        expired.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expired, 0).unwrap();
+        expired.price_lots = 91;
+        book.new_order(&mut eq, &mut market(), owner(1), 2, expired, 0).unwrap();
+
+        let mut capped_market = market();
+        capped_market.max_expired_own_side_drops_per_place = 1;
+        book.new_order(&mut eq, &mut capped_market, owner(2), 3, limit(Side::Bid, 95, 1), 20)
+            .unwrap();
+
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Expired);
+        assert!(eq.is_empty());
+        // Only one of the two expired resting bids was dropped; the other
+        // is still sitting in the tree alongside the new order.
+        assert_eq!(book.bids.tree().leaf_count(), 2);
+    }
+
+    #[test]
+    fn remove_expired_tags_removed_orders_with_expired_reason() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut expired = limit(Side::Bid, 90, 1);
+        expired.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expired, 0).unwrap();
+
+        book.remove_expired(&mut eq, Side::Bid, 20, 5).unwrap();
+
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Expired);
+    }
+
+    #[test]
+    fn remove_expired_stops_without_panicking_when_event_queue_is_full() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut expired = limit(Side::Bid, 90, 1);
+        expired.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expired, 0).unwrap();
+        expired.price_lots = 91;
+        book.new_order(&mut eq, &mut market(), owner(1), 2, expired, 0).unwrap();
+        expired.price_lots = 92;
+        book.new_order(&mut eq, &mut market(), owner(1), 3, expired, 0).unwrap();
+
+        // Leave room for exactly one more event before calling
+        // `remove_expired` directly.
+        while eq.len() < crate::state::event_queue::EVENT_QUEUE_CAPACITY - 1 {
+            eq.push_back(
+                OutEvent {
+                    event_type: crate::state::event_queue::EventType::Out,
+                    side: Side::Ask,
+                    owner: owner(9),
+                    order_id: 0,
+                    quantity_lots: 1,
+                    out_reason: crate::state::event_queue::OutReason::Cancelled,
+                    timestamp: 0,
+                    strategy_id: 0,
+                }
+                .into(),
+            )
+            .unwrap();
+        }
+
+        let removed = book.remove_expired(&mut eq, Side::Bid, 20, 5).unwrap();
+
+        assert_eq!(removed, 1);
+        assert!(eq.is_full());
+        // Cleanup stopped as soon as the queue filled up instead of
+        // erroring out or panicking; the other two expired orders are
+        // still sitting in the tree.
+        assert_eq!(book.bids.tree().leaf_count(), 2);
+    }
+
+    #[test]
+    fn cancel_expired_orders_sweeps_both_sides_up_to_a_shared_limit() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut expired = limit(Side::Bid, 90, 1);
+        expired.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expired, 0).unwrap();
+        expired.price_lots = 91;
+        book.new_order(&mut eq, &mut market(), owner(1), 2, expired, 0).unwrap();
+
+        let mut expired_ask = limit(Side::Ask, 200, 1);
+        expired_ask.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(2), 3, expired_ask, 0).unwrap();
+        expired_ask.price_lots = 201;
+        book.new_order(&mut eq, &mut market(), owner(2), 4, expired_ask, 0).unwrap();
+
+        let removed = book.cancel_expired_orders(&mut eq, 20, 3).unwrap();
+
+        assert_eq!(removed, 3);
+        // Both bids and one ask were swept before the shared limit hit.
+        assert_eq!(book.bids.tree().leaf_count(), 0);
+        assert_eq!(book.asks.tree().leaf_count(), 1);
+        for _ in 0..3 {
+            assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Expired);
+        }
+        assert!(eq.is_empty());
+    }
+
+    #[test]
+    fn cancel_expired_orders_works_with_millisecond_scale_timestamps_too() {
+        // `time_in_force`/`now_ts` are unit-agnostic integer comparisons
+        // (see `TimeInForce`'s doc comment), so passing millisecond
+        // values consistently exercises the exact same cleanup path a
+        // whole-seconds caller uses.
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut expired = limit(Side::Bid, 90, 1);
+        expired.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 1_500 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, expired, 0).unwrap();
+
+        assert_eq!(book.cancel_expired_orders(&mut eq, 1_499, 10).unwrap(), 0);
+        assert_eq!(book.bids.tree().leaf_count(), 1);
+
+        assert_eq!(book.cancel_expired_orders(&mut eq, 1_500, 10).unwrap(), 1);
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn cancel_owner_worst_removes_only_the_worst_priced_order() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 90, 1), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(1), 2, limit(Side::Bid, 95, 1), 0)
+            .unwrap();
+        // A different owner's order, at an even worse price, should be
+        // left alone.
+        book.new_order(&mut eq, &mut market(), owner(2), 3, limit(Side::Bid, 80, 1), 0)
+            .unwrap();
+
+        let cancelled = book
+            .cancel_owner_worst(&mut eq, owner(1), Side::Bid, 0)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(cancelled.order_id, 1);
+        assert_eq!(cancelled.price_lots, 90);
+        assert!(book
+            .bids
+            .tree()
+            .iter()
+            .all(|(_, order)| order.order_id != 1));
+        // The other owner's worse-priced order is still resting.
+        assert_eq!(book.bids.tree().leaf_count(), 2);
+    }
+
+    #[test]
+    fn cancel_owner_worst_is_none_when_owner_has_no_orders() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 90, 1), 0)
+            .unwrap();
+
+        assert_eq!(
+            book.cancel_owner_worst(&mut eq, owner(2), Side::Bid, 0).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn self_crossing_orders_returns_only_the_owners_orders_a_new_bid_would_cross() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        // Owner's own crossing ask.
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 1), 0)
+            .unwrap();
+        // Owner's own ask priced too high to cross.
+        book.new_order(&mut eq, &mut market(), owner(1), 2, limit(Side::Ask, 200, 1), 0)
+            .unwrap();
+        // A different owner's crossing ask, which isn't a self-cross.
+        book.new_order(&mut eq, &mut market(), owner(2), 3, limit(Side::Ask, 100, 1), 0)
+            .unwrap();
+
+        let crossing = book.self_crossing_orders(owner(1), Side::Bid, 100, 0);
+        let order_ids: Vec<u128> = crossing.iter().map(|(_, order)| order.order_id).collect();
+        assert_eq!(order_ids, vec![1]);
+    }
+
+    #[test]
+    fn self_crossing_orders_skips_expired_orders() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut order = limit(Side::Ask, 100, 1);
+        order.time_in_force = crate::state::order::TimeInForce::GoodTillTime { expiry_ts: 10 };
+        book.new_order(&mut eq, &mut market(), owner(1), 1, order, 0).unwrap();
+
+        assert!(book.self_crossing_orders(owner(1), Side::Bid, 100, 20).is_empty());
+    }
+
+    #[test]
+    fn cancel_orders_placed_before_only_cancels_stale_orders_up_to_the_limit() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 90, 1), 100)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(1), 2, limit(Side::Bid, 91, 1), 200)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(1), 3, limit(Side::Bid, 92, 1), 300)
+            .unwrap();
+        // A different owner's stale order should never be touched.
+        book.new_order(&mut eq, &mut market(), owner(2), 4, limit(Side::Bid, 93, 1), 100)
+            .unwrap();
+        // Resting placements that match nothing push no events.
+        assert!(eq.is_empty());
+
+        // Orders 1 and 2 predate 250, but the limit of 1 only lets one
+        // of them be cancelled.
+        let cancelled = book
+            .cancel_orders_placed_before(&mut eq, owner(1), Side::Bid, 250, 1, 999)
+            .unwrap();
+
+        assert_eq!(cancelled, 1);
+        assert_eq!(book.bids.tree().leaf_count(), 3);
+        assert_eq!(
+            out_reason_of(eq.pop_front().unwrap()),
+            crate::state::event_queue::OutReason::Cancelled
+        );
+        assert!(eq.is_empty());
+
+        // Order 3 (not stale) and order 4 (a different owner) are always
+        // left alone; exactly one of the two stale orders (1, 2) is gone.
+        let remaining_stale = [1u128, 2u128]
+            .iter()
+            .filter(|&&id| book.bids.tree().find_by_order_id(id).is_some())
+            .count();
+        assert_eq!(remaining_stale, 1);
+        assert!(book.bids.tree().find_by_order_id(3).is_some());
+        assert!(book.bids.tree().find_by_order_id(4).is_some());
+
+        // Cancelling again with the same cutoff finishes off the other
+        // stale order.
+        let cancelled = book
+            .cancel_orders_placed_before(&mut eq, owner(1), Side::Bid, 250, 1, 999)
+            .unwrap();
+        assert_eq!(cancelled, 1);
+        assert!(book.bids.tree().find_by_order_id(1).is_none());
+        assert!(book.bids.tree().find_by_order_id(2).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Orderbook.bids holds a BookSide built for the wrong side")]
+    fn placing_an_order_with_a_swapped_book_side_trips_the_debug_assert() {
+        let mut book = Orderbook::new();
+        book.bids = BookSide::new(Side::Ask);
+        let mut eq = EventQueue::new();
+
+        let _ = book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 100, 1), 0);
+    }
+
+    #[test]
+    fn new_order_rejects_non_positive_max_base_lots() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+
+        let mut zero = limit(Side::Bid, 99, 0);
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 1, zero, 0).unwrap_err(),
+            OrderbookError::InvalidQuantity
+        );
+
+        zero.max_base_lots = -1;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 2, zero, 0).unwrap_err(),
+            OrderbookError::InvalidQuantity
+        );
+    }
+
+    #[test]
+    fn new_order_rejects_non_positive_max_quote_lots() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+
+        let mut zero = limit(Side::Bid, 99, 10);
+        zero.max_quote_lots = 0;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 1, zero, 0).unwrap_err(),
+            OrderbookError::InvalidQuantity
+        );
+
+        zero.max_quote_lots = -1;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 2, zero, 0).unwrap_err(),
+            OrderbookError::InvalidQuantity
+        );
+    }
+
+    #[test]
+    fn new_order_rejects_non_positive_price_lots() {
+        // `price_lots` is the actual divisor in the matching loop
+        // (`remaining_quote / maker.price_lots`), so a negative or zero
+        // value here is at least as dangerous as a non-positive
+        // `max_base_lots`/`max_quote_lots` — see the two tests above.
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+
+        let mut zero = limit(Side::Bid, 0, 10);
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 1, zero, 0).unwrap_err(),
+            OrderbookError::InvalidPrice
+        );
+
+        zero.price_lots = -1;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 2, zero, 0).unwrap_err(),
+            OrderbookError::InvalidPrice
+        );
+    }
+
+    #[test]
+    fn new_order_rejects_a_resting_order_priced_below_min_price_lots() {
+        // A `Limit` order at price 0 (or negative) must never reach the
+        // book: were it to rest, the very next crossing order would
+        // divide by its price in the matching loop
+        // (`remaining_quote / maker.price_lots`) and panic.
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+
+        let mut zero_price = limit(Side::Bid, 0, 10);
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 1, zero_price, 0).unwrap_err(),
+            OrderbookError::InvalidPrice
+        );
+
+        zero_price.price_lots = -1;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(1), 2, zero_price, 0).unwrap_err(),
+            OrderbookError::InvalidPrice
+        );
+
+        assert!(book.bids.is_empty(), "the rejected orders must never have posted");
+    }
+
+    #[test]
+    fn market_orders_are_exempt_from_the_min_price_lots_check() {
+        // A `Market` order's `price_lots` is never used as a real limit
+        // (matching passes `None` for it), so a client leaving it at 0 or
+        // even negative must not be rejected on that basis alone.
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let market_buy = OrderParamsBuilder::new(Side::Bid, 0, 5).order_type(OrderType::Market).build();
+        let result = book.new_order(&mut eq, &mut market(), owner(2), 2, market_buy, 0).unwrap();
+        assert_eq!(result.unfilled_base_lots, 0);
+    }
+
+    #[test]
+    fn replace_order_cancels_old_and_posts_new() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 90, 5), 0)
+            .unwrap();
+
+        book.replace_order(
+            &mut eq,
+            &mut market(),
+            owner(1),
+            1,
+            Side::Bid,
+            2,
+            limit(Side::Bid, 95, 7),
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(out_reason_of(eq.pop_front().unwrap()), crate::state::event_queue::OutReason::Cancelled);
+        assert_eq!(book.bids.tree().leaf_count(), 1);
+        let resting = book.bids.best_order().unwrap();
+        assert_eq!(resting.order_id, 2);
+        assert_eq!(resting.price_lots, 95);
+        assert_eq!(resting.quantity_lots, 7);
+    }
+
+    #[test]
+    fn replace_order_failed_cancel_aborts_the_whole_operation() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 90, 5), 0)
+            .unwrap();
+
+        let err = book
+            .replace_order(
+                &mut eq,
+                &mut market(),
+                owner(2), // wrong owner: cancel should fail
+                1,
+                Side::Bid,
+                2,
+                limit(Side::Bid, 95, 7),
+                0,
+            )
+            .unwrap_err();
+
+        assert_eq!(err, OrderbookError::NotOrderOwner);
+        // The original order is untouched and the new one was never placed.
+        assert_eq!(book.bids.tree().leaf_count(), 1);
+        assert_eq!(book.bids.best_order().unwrap().order_id, 1);
+        assert!(eq.is_empty());
+    }
+
+    #[test]
+    fn checked_match_quote_lots_errors_instead_of_wrapping() {
+        // A high price times a large quantity overflows i64 rather than
+        // producing a wrapped, bogus notional.
+        let err = Orderbook::checked_match_quote_lots(i64::MAX / 2 + 1, 3).unwrap_err();
+        assert_eq!(err, OrderbookError::MathError);
+    }
+
+    #[test]
+    fn checked_match_quote_lots_succeeds_for_in_range_values() {
+        assert_eq!(Orderbook::checked_match_quote_lots(5, 100).unwrap(), 500);
+    }
+
+    #[test]
+    fn checked_debit_errors_instead_of_going_negative() {
+        // `amount` exceeding `remaining` would indicate a matching bug;
+        // in release this must return `MathError` rather than panic or
+        // silently underflow.
+        let err = Orderbook::checked_debit(5, 6).unwrap_err();
+        assert_eq!(err, OrderbookError::MathError);
+    }
+
+    #[test]
+    fn checked_debit_succeeds_for_in_range_values() {
+        assert_eq!(Orderbook::checked_debit(10, 4).unwrap(), 6);
+    }
+
+    #[test]
+    fn reduce_only_and_force_close_reject_new_placements() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+
+        let mut reduce_only = market();
+        reduce_only.mode = crate::state::market::MarketMode::ReduceOnly;
+        assert_eq!(
+            book.new_order(&mut eq, &mut reduce_only, owner(1), 1, limit(Side::Bid, 99, 1), 0)
+                .unwrap_err(),
+            OrderbookError::MarketNotAcceptingOrders
+        );
+
+        let mut force_close = market();
+        force_close.mode = crate::state::market::MarketMode::ForceClose;
+        assert_eq!(
+            book.new_order(&mut eq, &mut force_close, owner(1), 1, limit(Side::Bid, 99, 1), 0)
+                .unwrap_err(),
+            OrderbookError::MarketNotAcceptingOrders
+        );
+    }
+
+    #[test]
+    fn cancels_are_allowed_in_reduce_only_and_force_close_markets() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 1), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(1), 2, limit(Side::Bid, 98, 1), 0)
+            .unwrap();
+
+        // Cancellation methods take no market mode at all, so placement
+        // restrictions can never leak into them regardless of the
+        // market's current mode.
+        book.cancel_order_by_id(&mut eq, owner(1), Side::Bid, 1, 0)
+            .unwrap();
+        book.cancel_owner_worst(&mut eq, owner(1), Side::Bid, 0)
+            .unwrap();
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn cancel_order_by_id_refund_reports_quote_freed_for_a_bid() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 100, 5), 0)
+            .unwrap();
+
+        let (order, quote_freed, base_freed) = book
+            .cancel_order_by_id_refund(&mut eq, &market(), owner(1), Side::Bid, 1, 0)
+            .unwrap();
+
+        assert_eq!(order.quantity_lots, 5);
+        assert_eq!(base_freed, 0);
+        assert_eq!(quote_freed, 500);
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn cancel_order_by_id_refund_reports_base_freed_for_an_ask() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let (order, quote_freed, base_freed) = book
+            .cancel_order_by_id_refund(&mut eq, &market(), owner(1), Side::Ask, 1, 0)
+            .unwrap();
+
+        assert_eq!(order.quantity_lots, 5);
+        assert_eq!(quote_freed, 0);
+        assert_eq!(base_freed, 5);
+        assert!(book.asks.is_empty());
+    }
+
+    #[test]
+    fn resting_order_types_post_against_an_empty_book() {
+        for order_type in [OrderType::Limit, OrderType::PostOnly, OrderType::PostOnlySlide] {
+            let mut book = Orderbook::new();
+            let mut eq = EventQueue::new();
+            let mut params = limit(Side::Bid, 99, 10);
+            params.order_type = order_type;
+
+            book.new_order(&mut eq, &mut market(), owner(1), 1, params, 0).unwrap();
+
+            assert!(eq.is_empty(), "{order_type:?} should not generate any events");
+            assert_eq!(
+                book.bids.best_order().unwrap().quantity_lots,
+                10,
+                "{order_type:?} should post its full quantity"
+            );
+        }
+    }
+
+    #[test]
+    fn post_only_slide_adjusts_price_and_posts_instead_of_rejecting() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut slide = limit(Side::Bid, 105, 3);
+        slide.order_type = OrderType::PostOnlySlide;
+        book.new_order(&mut eq, &mut market(), owner(2), 2, slide, 0).unwrap();
+
+        assert!(eq.is_empty(), "sliding must never match, only reprice");
+        assert_eq!(book.bids.best_order().unwrap().price_lots, 99);
+        assert_eq!(book.bids.best_order().unwrap().quantity_lots, 3);
+    }
+
+    #[test]
+    fn post_only_slide_still_slides_and_posts_when_reduce_only() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut slide = limit(Side::Bid, 105, 3);
+        slide.order_type = OrderType::PostOnlySlide;
+        slide.reduce_only = true;
+        book.new_order(&mut eq, &mut market(), owner(2), 2, slide, 0).unwrap();
+
+        assert!(eq.is_empty(), "sliding must never match, only reprice");
+        assert_eq!(book.bids.best_order().unwrap().price_lots, 99);
+    }
+
+    #[test]
+    fn post_only_slide_lands_exactly_on_the_price_floor_when_allowed() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 2, 5), 0)
+            .unwrap();
+
+        let mut slide = limit(Side::Bid, 10, 3);
+        slide.order_type = OrderType::PostOnlySlide;
+        book.new_order(&mut eq, &mut market(), owner(2), 2, slide, 0).unwrap();
+
+        assert_eq!(book.bids.best_order().unwrap().price_lots, MIN_PRICE_LOTS);
+    }
+
+    #[test]
+    fn post_only_slide_refuses_when_the_floor_would_be_crossed() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 1, 5), 0)
+            .unwrap();
+
+        let mut slide = limit(Side::Bid, 10, 3);
+        slide.order_type = OrderType::PostOnlySlide;
+        assert_eq!(
+            book.new_order(&mut eq, &mut market(), owner(2), 2, slide, 0).unwrap_err(),
+            OrderbookError::PostOnlyWouldCross
+        );
+        assert!(book.bids.is_empty(), "a refused slide must not post at an invalid price");
+    }
+
+    #[test]
+    fn plain_post_only_still_rejects_a_crossing_price() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut post_only = limit(Side::Bid, 105, 3);
+        post_only.order_type = OrderType::PostOnly;
+        let err = book
+            .new_order(&mut eq, &mut market(), owner(2), 2, post_only, 0)
+            .unwrap_err();
+        assert_eq!(err, OrderbookError::PostOnlyWouldCross);
+    }
+
+    #[test]
+    fn bid_at_the_exact_best_ask_matches_when_match_on_touch_is_set() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut m = market();
+        m.match_on_touch = true;
+        book.new_order(&mut eq, &mut m, owner(2), 2, limit(Side::Bid, 100, 5), 0)
+            .unwrap();
+
+        let fill: FillEvent = eq.pop_front().unwrap().decode::<FillEvent>().unwrap().to_owned();
+        assert_eq!(fill.quantity_lots, 5);
+        assert!(book.asks.is_empty());
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn bid_at_the_exact_best_ask_posts_instead_when_match_on_touch_is_unset() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+
+        let mut m = market();
+        m.match_on_touch = false;
+        book.new_order(&mut eq, &mut m, owner(2), 2, limit(Side::Bid, 100, 5), 0)
+            .unwrap();
+
+        assert!(eq.is_empty());
+        assert_eq!(book.asks.best_order().unwrap().quantity_lots, 5);
+        assert_eq!(book.bids.best_order().unwrap().quantity_lots, 5);
+    }
+
+    #[test]
+    fn non_resting_order_types_are_a_no_op_against_an_empty_book() {
+        for order_type in [OrderType::Market, OrderType::ImmediateOrCancel] {
+            let mut book = Orderbook::new();
+            let mut eq = EventQueue::new();
+            let mut params = limit(Side::Bid, 99, 10);
+            params.order_type = order_type;
+
+            book.new_order(&mut eq, &mut market(), owner(1), 1, params, 0).unwrap();
+
+            assert!(eq.is_empty(), "{order_type:?} should not generate any events");
+            assert!(book.bids.is_empty(), "{order_type:?} should never rest on the book");
+        }
+    }
+
+    #[test]
+    fn caller_supplied_order_ids_are_findable_and_cancellable() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 42, limit(Side::Bid, 90, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 7, limit(Side::Bid, 85, 5), 0)
+            .unwrap();
+
+        assert!(book.bids.tree().find_by_order_id(42).is_some());
+        assert!(book.bids.tree().find_by_order_id(7).is_some());
+
+        book.cancel_order_by_id(&mut eq, owner(1), Side::Bid, 42, 0)
+            .unwrap();
+        assert!(book.bids.tree().find_by_order_id(42).is_none());
+        assert!(book.bids.tree().find_by_order_id(7).is_some());
+    }
+
+    #[test]
+    fn new_order_rejects_a_duplicate_order_id_already_resting() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 42, limit(Side::Bid, 90, 5), 0)
+            .unwrap();
+
+        let err = book
+            .new_order(&mut eq, &mut market(), owner(2), 42, limit(Side::Ask, 95, 5), 0)
+            .unwrap_err();
+        assert_eq!(err, OrderbookError::DuplicateKey);
+    }
+
+    #[test]
+    fn mostly_filled_ioc_is_not_penalized() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut penalized_market = market();
+        penalized_market.taker_fee_bps = 5;
+        penalized_market.fee_penalty_bps = 50;
+        penalized_market.fee_penalty_fill_threshold_bps = 5_000;
+
+        book.new_order(&mut eq, &mut penalized_market, owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+
+        let mut ioc = limit(Side::Bid, 100, 9);
+        ioc.order_type = OrderType::ImmediateOrCancel;
+        let taker_fee_bps = book
+            .new_order(&mut eq, &mut penalized_market, owner(2), 2, ioc, 0)
+            .unwrap()
+            .taker_fee_bps;
+
+        assert_eq!(taker_fee_bps, 5);
+    }
+
+    #[test]
+    fn barely_filled_ioc_is_penalized() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut penalized_market = market();
+        penalized_market.taker_fee_bps = 5;
+        penalized_market.fee_penalty_bps = 50;
+        penalized_market.fee_penalty_fill_threshold_bps = 5_000;
+
+        book.new_order(&mut eq, &mut penalized_market, owner(1), 1, limit(Side::Ask, 100, 1), 0)
+            .unwrap();
+
+        let mut ioc = limit(Side::Bid, 100, 10);
+        ioc.order_type = OrderType::ImmediateOrCancel;
+        let taker_fee_bps = book
+            .new_order(&mut eq, &mut penalized_market, owner(2), 2, ioc, 0)
+            .unwrap()
+            .taker_fee_bps;
+
+        assert_eq!(taker_fee_bps, 55);
+    }
+
+    #[test]
+    fn fee_penalty_never_applies_to_resting_order_types() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut penalized_market = market();
+        penalized_market.taker_fee_bps = 5;
+        penalized_market.fee_penalty_bps = 50;
+        penalized_market.fee_penalty_fill_threshold_bps = 5_000;
+
+        let taker_fee_bps = book
+            .new_order(&mut eq, &mut penalized_market, owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap()
+            .taker_fee_bps;
+
+        assert_eq!(taker_fee_bps, 5);
+    }
+
+    #[test]
+    fn market_order_refuses_fills_beyond_the_reference_price_band() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        // Best ask is 100, but the book is stale: a second, further-out
+        // ask at 200 is well outside a 10% band around a reference price
+        // of 100.
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 200, 5), 0)
+            .unwrap();
+
+        let buy = OrderParamsBuilder::new(Side::Bid, 0, 1_000)
+            .order_type(OrderType::Market)
+            .reference_price_guard(100, 1_000) // 10% band: refuse fills above 110.
+            .build();
+        let taker_fee_bps = book.new_order(&mut eq, &mut market(), owner(3), 3, buy, 0).unwrap().taker_fee_bps;
+
+        // Only the level within the band filled; the rest was dropped
+        // instead of sweeping into the stale level, and nothing posted
+        // since market orders never rest.
+        assert_eq!(eq.len(), 1);
+        assert_eq!(taker_fee_bps, market().taker_fee_bps);
+        assert_eq!(book.asks.best_order().unwrap().price_lots, 200);
+        assert_eq!(book.bids.best_order(), None);
+    }
+
+    #[test]
+    fn ioc_refuses_fills_beyond_the_reference_price_band_and_drops_the_remainder() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Ask, 100, 5), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut market(), owner(2), 2, limit(Side::Ask, 200, 5), 0)
+            .unwrap();
+
+        let buy = OrderParamsBuilder::new(Side::Bid, 1_000, 1_000)
+            .order_type(OrderType::ImmediateOrCancel)
+            .reference_price_guard(100, 1_000)
+            .build();
+        book.new_order(&mut eq, &mut market(), owner(3), 3, buy, 0).unwrap();
+
+        assert_eq!(eq.len(), 1);
+        assert_eq!(book.asks.best_order().unwrap().price_lots, 200);
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn total_orders_placed_counts_every_accepted_order() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut m = market();
+
+        book.new_order(&mut eq, &mut m, owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut m, owner(2), 2, limit(Side::Bid, 98, 5), 0)
+            .unwrap();
+
+        assert_eq!(m.total_orders_placed, 2);
+    }
+
+    #[test]
+    fn total_base_lots_filled_advances_on_matches_but_not_on_cancels() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut m = market();
+
+        book.new_order(&mut eq, &mut m, owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        assert_eq!(m.total_base_lots_filled, 0);
+
+        book.new_order(&mut eq, &mut m, owner(2), 2, limit(Side::Bid, 100, 4), 0)
+            .unwrap();
+        assert_eq!(m.total_base_lots_filled, 4);
+
+        // Cancelling the remainder of the resting maker order doesn't
+        // count as a fill.
+        book.cancel_order_by_id(&mut eq, owner(1), Side::Ask, 1, 0).unwrap();
+        assert_eq!(m.total_base_lots_filled, 4);
+    }
+
+    #[test]
+    fn total_base_lots_filled_counts_pro_rata_matches_too() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut m = market();
+        m.matching_policy = MatchingPolicy::ProRata;
+
+        book.new_order(&mut eq, &mut m, owner(1), 1, limit(Side::Ask, 100, 10), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut m, owner(2), 2, limit(Side::Ask, 100, 20), 0)
+            .unwrap();
+        book.new_order(&mut eq, &mut m, owner(3), 3, limit(Side::Bid, 100, 15), 0)
+            .unwrap();
+
+        assert_eq!(m.total_base_lots_filled, 15);
+    }
+
+    #[test]
+    fn ticker_one_sided_book() {
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        book.new_order(&mut eq, &mut market(), owner(1), 1, limit(Side::Bid, 99, 10), 0)
+            .unwrap();
+
+        let ticker = book.ticker(0);
+        assert_eq!(ticker.best_bid, Some(99));
+        assert_eq!(ticker.best_ask, None);
+        assert_eq!(ticker.ask_qty, None);
+        assert_eq!(ticker.mid, None);
+        assert_eq!(ticker.spread, None);
+    }
+
+    #[test]
+    fn book_is_never_left_crossed_after_random_crossing_placements() {
+        // A tiny xorshift PRNG, deterministic across runs, so this test
+        // doesn't need a `rand` dev-dependency just to fuzz prices.
+        let mut state: u64 = 0x9E3779B97F4A7C15;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        for i in 0..500u128 {
+            let side = if next() % 2 == 0 { Side::Bid } else { Side::Ask };
+            // Prices drawn from a narrow range so bids and asks
+            // frequently cross and exercise the match loop.
+            let price = 90 + (next() % 20) as i64;
+            let qty = 1 + (next() % 5) as i64;
+
+            book.new_order(&mut eq, &mut market(), owner((i % 4) as u8), i, limit(side, price, qty), 0)
+                .unwrap();
+
+            assert!(!book.is_crossed(0), "book crossed after placement {i}");
+
+            // Drain events so the queue never fills up over 500 placements.
+            while eq.pop_front().is_ok() {}
+        }
+    }
+
+    /// A fill, as reported by the reference matcher below: which owner
+    /// paid whom, at what price and quantity. Compared field-for-field
+    /// against the real book's `FillEvent`s.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct ReferenceFill {
+        maker: Pubkey,
+        taker: Pubkey,
+        price_lots: i64,
+        quantity_lots: i64,
+    }
+
+    /// A deliberately naive reference matcher for `GoodTillCancel` limit
+    /// orders under `SelfTradeBehavior::DecrementTake`, used only to
+    /// differentially test the real, index-based [`Orderbook`] against
+    /// an independent implementation. Resting orders live in plain
+    /// `Vec`s and the best one is found by a linear scan every time,
+    /// rather than the real book's price/time-keyed index — slow, but
+    /// simple enough to trust by inspection.
+    struct ReferenceBook {
+        bids: Vec<Order>,
+        asks: Vec<Order>,
+    }
+
+    impl ReferenceBook {
+        fn new() -> Self {
+            Self { bids: Vec::new(), asks: Vec::new() }
+        }
+
+        fn side_mut(&mut self, side: Side) -> &mut Vec<Order> {
+            match side {
+                Side::Bid => &mut self.bids,
+                Side::Ask => &mut self.asks,
+            }
+        }
+
+        /// The index of the best non-expired resting order on `side`, if
+        /// any: highest price for bids, lowest for asks, earliest
+        /// insertion breaking a tie either way.
+        fn best_index(&self, side: Side, now_ts: i64) -> Option<usize> {
+            match side {
+                Side::Bid => self
+                    .bids
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, o)| !o.is_expired(now_ts))
+                    .max_by_key(|(i, o)| (o.price_lots, std::cmp::Reverse(*i)))
+                    .map(|(i, _)| i),
+                Side::Ask => self
+                    .asks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, o)| !o.is_expired(now_ts))
+                    .min_by_key(|(i, o)| (o.price_lots, *i))
+                    .map(|(i, _)| i),
+            }
+        }
+
+        /// Removes `quantity` from the resting order at `idx`, dropping
+        /// it entirely once nothing is left.
+        fn decrement_or_remove(&mut self, side: Side, idx: usize, quantity: i64) {
+            let orders = self.side_mut(side);
+            orders[idx].quantity_lots -= quantity;
+            if orders[idx].quantity_lots <= 0 {
+                orders.remove(idx);
+            }
+        }
+
+        /// Places a `GoodTillCancel` limit order, matching it against the
+        /// opposing side first and posting whatever's left. Mirrors
+        /// `Orderbook::new_order`'s `DecrementTake` self-trade handling
+        /// and quote-budget stopping condition.
+        fn place(
+            &mut self,
+            owner: Pubkey,
+            order_id: u128,
+            side: Side,
+            price_lots: i64,
+            quantity_lots: i64,
+            now_ts: i64,
+        ) -> Vec<ReferenceFill> {
+            let maker_side = side.invert_side();
+            let mut fills = Vec::new();
+            let mut remaining_base = quantity_lots;
+            let mut remaining_quote = i64::MAX;
+
+            while remaining_base > 0 {
+                let Some(idx) = self.best_index(maker_side, now_ts) else { break };
+                let maker = self.side_mut(maker_side)[idx];
+                if !Side::would_cross(side, price_lots, maker.price_lots) {
+                    break;
+                }
+                if maker.owner == owner {
+                    let self_match = remaining_base.min(maker.quantity_lots);
+                    remaining_base -= self_match;
+                    self.decrement_or_remove(maker_side, idx, self_match);
+                    continue;
+                }
+                let affordable_base = remaining_quote / maker.price_lots;
+                if affordable_base <= 0 {
+                    break;
+                }
+                let match_quantity = remaining_base.min(affordable_base).min(maker.quantity_lots);
+                fills.push(ReferenceFill {
+                    maker: maker.owner,
+                    taker: owner,
+                    price_lots: maker.price_lots,
+                    quantity_lots: match_quantity,
+                });
+                remaining_base -= match_quantity;
+                remaining_quote -= match_quantity * maker.price_lots;
+                self.decrement_or_remove(maker_side, idx, match_quantity);
+            }
+
+            if remaining_base > 0 {
+                self.side_mut(side).push(Order {
+                    order_id,
+                    owner,
+                    side,
+                    price_lots,
+                    quantity_lots: remaining_base,
+                    order_type: OrderType::Limit,
+                    time_in_force: crate::state::order::TimeInForce::GoodTillCancel,
+                    timestamp: now_ts,
+                    client_order_id: 0,
+                    strategy_id: 0,
+                });
+            }
+            fills
+        }
+
+        /// This side's resting orders as `(owner, price_lots,
+        /// quantity_lots)`, sorted for order-independent comparison
+        /// against the real book's snapshot.
+        fn sorted_snapshot(&self, side: Side) -> Vec<(Pubkey, i64, i64)> {
+            let orders = match side {
+                Side::Bid => &self.bids,
+                Side::Ask => &self.asks,
+            };
+            let mut snapshot: Vec<(Pubkey, i64, i64)> =
+                orders.iter().map(|o| (o.owner, o.price_lots, o.quantity_lots)).collect();
+            snapshot.sort();
+            snapshot
+        }
+    }
+
+    fn book_side_snapshot(side: &BookSide) -> Vec<(Pubkey, i64, i64)> {
+        let mut snapshot: Vec<(Pubkey, i64, i64)> = side
+            .iter_all_including_invalid()
+            .map(|o| (o.owner, o.price_lots, o.quantity_lots))
+            .collect();
+        snapshot.sort();
+        snapshot
+    }
+
+    #[test]
+    fn real_book_matches_the_reference_model_across_a_random_order_stream() {
+        // Same tiny xorshift PRNG as `book_is_never_left_crossed_after_random_crossing_placements`,
+        // seeded so a failure is reproducible by hardcoding this value.
+        let seed: u64 = 0xD1CE_5EED_C0FF_EE01;
+        let mut state = seed;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut book = Orderbook::new();
+        let mut eq = EventQueue::new();
+        let mut reference = ReferenceBook::new();
+
+        for i in 0..300u128 {
+            let side = if next() % 2 == 0 { Side::Bid } else { Side::Ask };
+            // A narrow price range and a handful of owners keep both
+            // crossing fills and same-owner self-trades common.
+            let price = 95 + (next() % 10) as i64;
+            let qty = 1 + (next() % 5) as i64;
+            let taker = owner((next() % 3) as u8);
+
+            book.new_order(&mut eq, &mut market(), taker, i, limit(side, price, qty), 0)
+                .unwrap();
+            let mut real_fills = Vec::new();
+            while let Ok(event) = eq.pop_front() {
+                if let Ok(fill) = event.decode::<FillEvent>() {
+                    real_fills.push(ReferenceFill {
+                        maker: fill.maker,
+                        taker: fill.taker,
+                        price_lots: fill.price_lots,
+                        quantity_lots: fill.quantity_lots,
+                    });
+                }
+            }
+
+            let reference_fills = reference.place(taker, i, side, price, qty, 0);
+
+            assert_eq!(
+                real_fills, reference_fills,
+                "fill mismatch at step {i} (seed {seed:#x})"
+            );
+            assert_eq!(
+                book_side_snapshot(&book.bids),
+                reference.sorted_snapshot(Side::Bid),
+                "bid book mismatch at step {i} (seed {seed:#x})"
+            );
+            assert_eq!(
+                book_side_snapshot(&book.asks),
+                reference.sorted_snapshot(Side::Ask),
+                "ask book mismatch at step {i} (seed {seed:#x})"
+            );
+        }
+    }
+}